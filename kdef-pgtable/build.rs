@@ -6,28 +6,40 @@ fn main() {
 
     let mut va_bits = 48usize;
     let mut pg_va_bits = 48usize;
-    let mut page_levels = 4usize;
     let mut page_shift = 12usize;
 
     let target = std::env::var("TARGET").unwrap();
+    let aarch64 = target.contains("aarch64-");
 
-    if target.contains("aarch64-") {
-        va_bits = 52;
+    let sz16k = std::env::var("CARGO_FEATURE_PG_SZ16K").is_ok();
+    let sz64k = std::env::var("CARGO_FEATURE_PG_SZ64K").is_ok();
+    if sz16k && sz64k {
+        panic!("features `pg-sz16k` and `pg-sz64k` are mutually exclusive");
     }
 
-    if std::env::var("CARGO_FEATURE_PG_SZ16K").is_ok() {
+    if sz16k {
         page_shift = 14;
-        if target.contains("aarch64-") {
+        if aarch64 {
             pg_va_bits = 47;
         }
     }
 
+    if sz64k {
+        page_shift = 16;
+        // 64K pages reach 52 bits without LPA2 (FEAT_LVA).
+        if aarch64 {
+            va_bits = 52;
+        }
+    }
+
+    // `pg-l3` only picks the layout the kernel is linked for, the loader
+    // widens it up to `VA_BITS` when the CPU allows.
     if std::env::var("CARGO_FEATURE_PG_L3").is_ok() {
-        va_bits = 39;
-        pg_va_bits = 39;
-        page_levels = 3;
+        pg_va_bits = pg_va_bits.min(page_shift + 3 * (page_shift - 3));
     }
 
+    let page_levels = (pg_va_bits - page_shift).div_ceil(page_shift - 3);
+
     let const_content = quote! {
         pub const VA_BITS: usize = #va_bits;
         pub const PG_VA_BITS: usize = #pg_va_bits;
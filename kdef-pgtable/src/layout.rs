@@ -0,0 +1,135 @@
+use crate::{PG_VA_BITS, SZ_2M};
+
+/// Size of the fixmap window, just below the kernel image.
+pub const FIXMAP_VSIZE: usize = 2 * SZ_2M;
+
+/// A named range of the kernel virtual address space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmRegion {
+    pub name: &'static str,
+    pub start: usize,
+    pub size: usize,
+}
+
+impl VmRegion {
+    pub const fn new(name: &'static str, start: usize, size: usize) -> Self {
+        Self { name, start, size }
+    }
+
+    /// Exclusive end, wraps to 0 for a region reaching the top of the address space.
+    pub const fn end(&self) -> usize {
+        self.start.wrapping_add(self.size)
+    }
+
+    /// Last address inside the region.
+    pub const fn last(&self) -> usize {
+        self.start + (self.size - 1)
+    }
+
+    pub const fn contains(&self, vaddr: usize) -> bool {
+        self.size > 0 && vaddr >= self.start && vaddr <= self.last()
+    }
+
+    pub const fn overlaps(&self, other: &VmRegion) -> bool {
+        self.size > 0 && other.size > 0 && self.start <= other.last() && other.start <= self.last()
+    }
+}
+
+/// Kernel virtual address space.
+///
+/// Regions are carved in units of the kernel image window `U`, relative to
+/// the start of the module area:
+///
+/// ```text
+/// [0U, 1U)  modules
+/// [1U, 5U)  vmalloc
+/// [5U, 7U)  io
+/// [7U, 8U)  guard, fixmap at its top
+/// [8U, 9U)  kernel image
+/// ```
+///
+/// The linear map takes the rest: above the kernel image for the high-half
+/// layout, `[0, modules)` with `space-low`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmLayout {
+    pub modules: VmRegion,
+    pub vmalloc: VmRegion,
+    pub io: VmRegion,
+    pub fixmap: VmRegion,
+    pub kimage: VmRegion,
+    pub linear: VmRegion,
+}
+
+impl VmLayout {
+    /// The layout for a `va_bits` wide address space, `low` for `space-low`.
+    pub const fn new(va_bits: usize, low: bool) -> Self {
+        let (base, unit) = if low {
+            (
+                (1usize << va_bits) / 0x10 * 0xF,
+                (1usize << va_bits) / 0x100,
+            )
+        } else {
+            (!((1usize << va_bits) - 1), (1usize << va_bits) / 0x10)
+        };
+        let linear = if low {
+            VmRegion::new("linear", 0, base)
+        } else {
+            let start = base + 9 * unit;
+            VmRegion::new("linear", start, start.wrapping_neg())
+        };
+        Self::from_parts(base, unit, linear)
+    }
+
+    pub(crate) const fn from_parts(base: usize, unit: usize, linear: VmRegion) -> Self {
+        let kimage = base + 8 * unit;
+        Self {
+            modules: VmRegion::new("modules", base, unit),
+            vmalloc: VmRegion::new("vmalloc", base + unit, 4 * unit),
+            io: VmRegion::new("io", base + 5 * unit, 2 * unit),
+            fixmap: VmRegion::new("fixmap", kimage - FIXMAP_VSIZE, FIXMAP_VSIZE),
+            kimage: VmRegion::new("kimage", kimage, unit),
+            linear,
+        }
+    }
+
+    pub const fn regions(&self) -> [VmRegion; 6] {
+        [
+            self.modules,
+            self.vmalloc,
+            self.io,
+            self.fixmap,
+            self.kimage,
+            self.linear,
+        ]
+    }
+
+    /// Returns the region containing `vaddr`.
+    pub fn find(&self, vaddr: usize) -> Option<VmRegion> {
+        self.regions().into_iter().find(|r| r.contains(vaddr))
+    }
+
+    /// Panics if any two regions overlap or a region is empty.
+    pub const fn assert_valid(&self) {
+        let regions = self.regions();
+        let mut i = 0;
+        while i < regions.len() {
+            assert!(regions[i].size > 0, "empty virtual memory region");
+            let mut j = i + 1;
+            while j < regions.len() {
+                assert!(
+                    !regions[i].overlaps(&regions[j]),
+                    "virtual memory regions overlap"
+                );
+                j += 1;
+            }
+            i += 1;
+        }
+    }
+}
+
+/// The layout the kernel is linked for.
+pub const fn layout() -> VmLayout {
+    VmLayout::new(PG_VA_BITS, cfg!(feature = "space-low"))
+}
+
+const _: () = layout().assert_valid();
@@ -2,6 +2,12 @@
 
 include!(concat!(env!("OUT_DIR"), "/constant.rs"));
 
+mod layout;
+mod va;
+
+pub use layout::*;
+pub use va::*;
+
 pub const SZ_1G: usize = 1024 * SZ_1M;
 pub const SZ_2G: usize = 2 * SZ_1G;
 pub const SZ_1M: usize = 1024 * 1024;
@@ -11,45 +17,129 @@ pub const SZ_16M: usize = 16 * SZ_1M;
 
 pub const PAGE_SIZE: usize = 1usize << PAGE_SHIFT;
 
-const MODULES_VADDR: usize = _page_end(PG_VA_BITS);
+pub const KIMAGE_VSIZE: usize = layout().kimage.size;
 
-#[cfg(not(feature = "space-low"))]
-const VSIZE_P: usize = 0x10;
+pub const KIMAGE_VADDR: usize = layout().kimage.start;
 
-#[cfg(feature = "space-low")]
-const VSIZE_P: usize = 0x100;
+pub const KLINER_OFFSET: usize = layout().linear.start;
 
-const MODULES_VSIZE: usize = (1usize << PG_VA_BITS) / VSIZE_P * 0x8;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-pub const KIMAGE_VSIZE: usize = (1usize << PG_VA_BITS) / VSIZE_P;
+    #[test]
+    fn test_page_levels() {
+        assert_eq!(page_levels(12, 39), 3);
+        assert_eq!(page_levels(12, 48), 4);
+        assert_eq!(page_levels(14, 47), 3);
+        assert_eq!(page_levels(14, 48), 4);
+        assert_eq!(page_levels(16, 42), 2);
+        assert_eq!(page_levels(16, 52), 3);
+    }
 
-pub const KIMAGE_VADDR: usize = MODULES_VADDR + MODULES_VSIZE;
+    #[test]
+    fn test_linked() {
+        let linked = VaLayout::LINKED;
+        assert_eq!(linked.page_size(), PAGE_SIZE);
+        assert_eq!(linked.page_levels, page_levels(PAGE_SHIFT, PG_VA_BITS));
+        assert_eq!(linked.with_va_bits(PG_VA_BITS), linked);
+    }
 
-#[cfg(not(feature = "space-low"))]
-pub const KLINER_OFFSET: usize = KIMAGE_VADDR + KIMAGE_VSIZE;
-#[cfg(feature = "space-low")]
-pub const KLINER_OFFSET: usize = 0;
+    #[test]
+    fn test_with_va_bits() {
+        let linked = VaLayout {
+            page_shift: 12,
+            va_bits: 39,
+            page_levels: 3,
+            kimage_vaddr: 0xffff_ffc0_0000_0000,
+            kimage_vsize: 0x8_0000_0000,
+            kliner_offset: 0xffff_ffe0_0000_0000,
+        };
+        let layout = linked.with_va_bits(48);
+        assert_eq!(layout.page_levels, 4);
+        assert_eq!(layout.kimage_vaddr, linked.kimage_vaddr);
+        assert_eq!(layout.kliner_offset, 0xffff_0000_0000_0000);
 
-const fn _page_offset(va: usize) -> usize {
-    !((1usize << va) - 1)
-}
+        let low = VaLayout {
+            kliner_offset: 0,
+            ..linked
+        };
+        assert_eq!(low.with_va_bits(48).kliner_offset, 0);
+    }
 
-#[cfg(not(feature = "space-low"))]
-const fn _page_end(va: usize) -> usize {
-    !((1usize << va) - 1)
-}
-#[cfg(feature = "space-low")]
-const fn _page_end(va: usize) -> usize {
-    (1usize << va) / 0x10 * 0xF
-}
+    #[test]
+    fn test_layout_all_features() {
+        // (pg-sz16k, pg-l3) x space-low
+        for va_bits in [48, 47, 39] {
+            for low in [false, true] {
+                VmLayout::new(va_bits, low).assert_valid();
+            }
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_layout_addrs() {
+        let high = VmLayout::new(48, false);
+        assert_eq!(high.modules.start, 0xffff_0000_0000_0000);
+        assert_eq!(high.kimage.start, 0xffff_8000_0000_0000);
+        assert_eq!(high.kimage.size, 0x1000_0000_0000);
+        assert_eq!(high.linear.start, 0xffff_9000_0000_0000);
+        assert_eq!(high.linear.end(), 0);
+        assert_eq!(high.fixmap.end(), high.kimage.start);
+
+        let low = VmLayout::new(48, true);
+        assert_eq!(low.modules.start, 0xf000_0000_0000);
+        assert_eq!(low.kimage.start, 0xf800_0000_0000);
+        assert_eq!(low.kimage.size, 0x100_0000_0000);
+        assert_eq!(low.linear.start, 0);
+        assert_eq!(low.linear.end(), low.modules.start);
+
+        assert_eq!(high.find(0xffff_1000_0000_0000), Some(high.vmalloc));
+        assert_eq!(high.find(0xffff_7fff_ffff_f000), Some(high.fixmap));
+        assert_eq!(high.find(0x1000), None);
+    }
 
     #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
+    fn test_runtime_layout() {
+        assert_eq!(VaLayout::LINKED.vm_layout(), layout());
+
+        for low in [false, true] {
+            let linked = VmLayout::new(39, low);
+            let va = VaLayout {
+                page_shift: 12,
+                va_bits: 39,
+                page_levels: 3,
+                kimage_vaddr: linked.kimage.start,
+                kimage_vsize: linked.kimage.size,
+                kliner_offset: linked.linear.start,
+            };
+            assert_eq!(va.vm_layout(), linked);
+
+            let wide = va.with_va_bits(48).vm_layout();
+            wide.assert_valid();
+            assert_eq!(wide.kimage, linked.kimage);
+            assert_eq!(wide.vmalloc, linked.vmalloc);
+        }
+    }
+
+    #[test]
+    fn test_layout_64k() {
+        let linked = VmLayout::new(48, false);
+        let va = VaLayout {
+            page_shift: 16,
+            va_bits: 48,
+            page_levels: page_levels(16, 48),
+            kimage_vaddr: linked.kimage.start,
+            kimage_vsize: linked.kimage.size,
+            kliner_offset: linked.linear.start,
+        };
+        assert_eq!(va.page_levels, 3);
+
+        let wide = va.with_va_bits(52);
+        assert_eq!(wide.page_levels, 3);
+        assert_eq!(wide.kliner_offset, 0xfff0_0000_0000_0000);
+        let vm = wide.vm_layout();
+        vm.assert_valid();
+        assert_eq!(vm.linear.end(), vm.modules.start);
     }
 }
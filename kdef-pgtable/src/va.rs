@@ -0,0 +1,88 @@
+use crate::{
+    KIMAGE_VADDR, KIMAGE_VSIZE, KLINER_OFFSET, PAGE_LEVELS, PAGE_SHIFT, PG_VA_BITS, VA_BITS,
+    VmLayout, VmRegion,
+};
+
+/// Virtual address space layout of the running kernel.
+///
+/// The kernel is linked for [`VaLayout::LINKED`], the loader widens it to what
+/// the CPU supports (see [`VaLayout::with_va_bits`]) and hands the result over
+/// in the boot info. Code that needs the linear map offset at runtime must
+/// read it from here instead of [`KLINER_OFFSET`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VaLayout {
+    pub page_shift: usize,
+    /// Width of the translated virtual address, `64 - TnSZ`.
+    pub va_bits: usize,
+    /// Levels of the root page table.
+    pub page_levels: usize,
+    pub kimage_vaddr: usize,
+    /// Size of the window the kernel image may be placed in.
+    pub kimage_vsize: usize,
+    /// `vaddr = paddr + kliner_offset` for the linear map.
+    pub kliner_offset: usize,
+}
+
+impl VaLayout {
+    /// The layout selected by the crate features at build time.
+    pub const LINKED: Self = Self {
+        page_shift: PAGE_SHIFT,
+        va_bits: PG_VA_BITS,
+        page_levels: PAGE_LEVELS,
+        kimage_vaddr: KIMAGE_VADDR,
+        kimage_vsize: KIMAGE_VSIZE,
+        kliner_offset: KLINER_OFFSET,
+    };
+
+    /// The widest virtual address the kernel accepts at runtime.
+    pub const MAX_VA_BITS: usize = VA_BITS;
+
+    pub const fn page_size(&self) -> usize {
+        1 << self.page_shift
+    }
+
+    /// Virtual memory regions of this layout, only the linear map differs
+    /// from the linked [`layout()`](crate::layout).
+    pub const fn vm_layout(&self) -> VmLayout {
+        let unit = self.kimage_vsize;
+        let base = self.kimage_vaddr - 8 * unit;
+        let size = if self.kliner_offset < base {
+            base - self.kliner_offset
+        } else {
+            self.kliner_offset.wrapping_neg()
+        };
+        VmLayout::from_parts(
+            base,
+            unit,
+            VmRegion::new("linear", self.kliner_offset, size),
+        )
+    }
+
+    /// Returns the layout for a `va_bits` wide address space.
+    ///
+    /// The kernel image stays where it was linked. With the high-half layout
+    /// the space gained below the linked one becomes the linear map, the
+    /// low layout (`kliner_offset == 0`) is kept as is.
+    pub const fn with_va_bits(self, va_bits: usize) -> Self {
+        assert!(
+            va_bits >= self.va_bits,
+            "va_bits is smaller than the linked layout"
+        );
+
+        let mut layout = self;
+        if va_bits > self.va_bits && self.kliner_offset != 0 {
+            layout.kliner_offset = !((1usize << va_bits) - 1);
+        }
+        layout.va_bits = va_bits;
+        layout.page_levels = page_levels(self.page_shift, va_bits);
+        layout
+    }
+}
+
+/// Number of translation levels needed to resolve `va_bits` with
+/// `1 << page_shift` byte pages.
+pub const fn page_levels(page_shift: usize, va_bits: usize) -> usize {
+    let bits_per_level = page_shift - 3;
+    (va_bits - page_shift).div_ceil(bits_per_level)
+}
@@ -1,18 +1,32 @@
 use core::mem::MaybeUninit;
 
+use pie_boot_if::VaLayout;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CacheKind {
+    /// Normal, write-back
     Normal,
+    /// Device-nGnRE
     Device,
+    /// Normal, non-cacheable
     NoCache,
+    /// Normal, non-cacheable, for framebuffers and prefetchable BARs
+    WriteCombining,
+    /// Normal, write-through
+    WriteThrough,
+    /// Device-nGnRnE
+    DeviceStrict,
 }
 
 impl CacheKind {
+    /// Index into the MAIR set up by `setup_table_regs`, the same at EL1 and EL2.
     pub fn mair_idx(&self) -> u64 {
         match self {
             CacheKind::Device => 0,
             CacheKind::Normal => 1,
-            CacheKind::NoCache => 2,
+            CacheKind::NoCache | CacheKind::WriteCombining => 2,
+            CacheKind::WriteThrough => 3,
+            CacheKind::DeviceStrict => 4,
         }
     }
 }
@@ -21,15 +35,22 @@ impl CacheKind {
 #[derive(Clone)]
 pub struct EarlyBootArgs {
     pub args: [usize; 4],
+    /// [`pie_boot_if::BOOT_MAGIC`] written by the kernel, checked before anything else is used.
+    pub magic: usize,
     pub virt_entry: *mut (),
     pub kimage_addr_lma: *mut (),
     pub kimage_addr_vma: *mut (),
     pub stack_top_lma: *mut (),
     pub stack_top_vma: *mut (),
     pub kcode_end: *mut (),
+    /// `.rela.dyn` of the kernel, empty if it is not linked with `-pie`.
+    pub rela_start: *mut (),
+    pub rela_end: *mut (),
     pub el: usize,
-    pub kliner_offset: usize,
-    pub page_size: usize,
+    /// The layout the kernel is linked for.
+    pub layout: VaLayout,
+    /// The widest virtual address the kernel accepts.
+    pub va_bits_max: usize,
     pub debug: usize,
 }
 
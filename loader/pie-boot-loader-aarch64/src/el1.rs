@@ -2,7 +2,7 @@ use core::arch::asm;
 
 use crate::{
     def::CacheKind,
-    paging::{PTEGeneric, PhysAddr, TableGeneric, VirtAddr},
+    paging::{PTEGeneric, PhysAddr, TableGeneric, VirtAddr, granule},
 };
 use aarch64_cpu::{asm::*, registers::*};
 use aarch64_cpu_ext::asm::tlb::{VAAE1IS, VMALLE1, tlbi};
@@ -186,10 +186,10 @@ bitflags::bitflags! {
 
 #[repr(transparent)]
 #[derive(Clone, Copy)]
-pub struct Pte(usize);
+pub struct Pte<const PAGE_SHIFT: usize>(usize);
 
-impl Pte {
-    const PHYS_ADDR_MASK: usize = 0x0000_ffff_ffff_f000; // bits 12..48
+impl<const PAGE_SHIFT: usize> Pte<PAGE_SHIFT> {
+    const PHYS_ADDR_MASK: usize = granule::phys_addr_mask(PAGE_SHIFT);
     const MAIR_MASK: usize = 0b111 << 2;
 
     #[inline(always)]
@@ -210,25 +210,23 @@ impl Pte {
             | PteFlags::NON_BLOCK
             | PteFlags::UXN;
 
-        let idx = match cache {
-            CacheKind::Device => 0,
-            CacheKind::Normal => {
+        match cache {
+            CacheKind::Device | CacheKind::DeviceStrict => {}
+            CacheKind::Normal | CacheKind::WriteThrough => {
                 flags |= PteFlags::INNER | PteFlags::SHAREABLE;
-                1
             }
-            CacheKind::NoCache => {
+            CacheKind::NoCache | CacheKind::WriteCombining => {
                 flags |= PteFlags::SHAREABLE;
-                2
             }
-        };
+        }
 
         let mut s = Self(flags.bits());
-        s.set_mair_idx(idx);
+        s.set_mair_idx(cache.mair_idx() as usize);
         s
     }
 }
 
-impl PTEGeneric for Pte {
+impl<const PAGE_SHIFT: usize> PTEGeneric for Pte<PAGE_SHIFT> {
     #[inline(always)]
     fn valid(&self) -> bool {
         self.as_flags().contains(PteFlags::VALID)
@@ -270,17 +268,21 @@ impl PTEGeneric for Pte {
     }
 }
 
-impl core::fmt::Debug for Pte {
+impl<const PAGE_SHIFT: usize> core::fmt::Debug for Pte<PAGE_SHIFT> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "PTE {:?}", self.paddr())
     }
 }
 
 #[derive(Clone, Copy)]
-pub struct Table;
-
-impl TableGeneric for Table {
-    type PTE = Pte;
+pub struct Table<const PAGE_SHIFT: usize>;
+
+impl<const PAGE_SHIFT: usize> TableGeneric for Table<PAGE_SHIFT> {
+    type PTE = Pte<PAGE_SHIFT>;
+    const PAGE_SIZE: usize = 1 << PAGE_SHIFT;
+    const VALID_BITS: usize = granule::valid_bits(PAGE_SHIFT);
+    const LEVEL: usize = granule::levels(PAGE_SHIFT);
+    const MAX_BLOCK_LEVEL: usize = granule::max_block_level(PAGE_SHIFT);
 
     fn flush(vaddr: Option<VirtAddr>) {
         flush_tlb(vaddr.map(|o| o.raw().into()));
@@ -0,0 +1,103 @@
+use core::{arch::asm, ptr::NonNull};
+
+use aarch64_cpu::registers::*;
+use fdt_parser::Fdt;
+use num_align::NumAlign;
+use pie_boot_if::VaLayout;
+
+use crate::def::EarlyBootArgs;
+
+const SZ_2M: usize = 0x20_0000;
+const SZ_1G: usize = 0x4000_0000;
+
+/// 选择内核镜像的随机偏移，2M 对齐，保证镜像及其后的启动页表仍落在
+/// `KIMAGE_VSIZE` 范围内。
+///
+/// 内核未以 `-pie` 链接、`bootargs` 含 `nokaslr` 或没有熵源时返回 0。
+pub fn offset(args: &EarlyBootArgs, layout: &VaLayout, fdt: usize) -> usize {
+    if args.rela_end <= args.rela_start {
+        printkv!("KASLR", "disabled, kernel is not relocatable");
+        return 0;
+    }
+
+    let Some(base) = NonNull::new(fdt as *mut u8) else {
+        return 0;
+    };
+    let Ok(fdt) = Fdt::from_ptr(base) else {
+        return 0;
+    };
+
+    let chosen = fdt.find_nodes("/chosen").next();
+    let nokaslr = chosen
+        .as_ref()
+        .and_then(|c| c.find_property("bootargs"))
+        .is_some_and(|p| p.str().split_ascii_whitespace().any(|a| a == "nokaslr"));
+
+    // 即使不使用，也要清除设备树中的种子，避免传给内核后泄露
+    let mut seed = None;
+    if let Some(chosen) = &chosen {
+        for name in ["kaslr-seed", "rng-seed"] {
+            if let Some(prop) = chosen.find_property(name) {
+                let raw = prop.raw_value();
+                for chunk in raw.chunks(8) {
+                    let mut buf = [0u8; 8];
+                    buf[..chunk.len()].copy_from_slice(chunk);
+                    seed = Some(mix(seed.unwrap_or(0), u64::from_be_bytes(buf)));
+                }
+                wipe(base, raw);
+            }
+        }
+    }
+    if let Some(v) = rndr() {
+        seed = Some(mix(seed.unwrap_or(0), v));
+    }
+
+    if nokaslr {
+        printkv!("KASLR", "disabled by `nokaslr`");
+        return 0;
+    }
+    let Some(seed) = seed else {
+        printkv!("KASLR", "disabled, no entropy");
+        return 0;
+    };
+
+    let image_size = args.kcode_end as usize - args.kimage_addr_lma as usize;
+    // `new_boot_table` 按 1G/512M 向上取整映射镜像及其后的页表
+    let span = image_size.align_up(SZ_1G) + SZ_1G;
+    let slots = layout.kimage_vsize.saturating_sub(span) / SZ_2M;
+    if slots == 0 {
+        return 0;
+    }
+    (seed % slots as u64) as usize * SZ_2M
+}
+
+/// splitmix64
+fn mix(seed: u64, v: u64) -> u64 {
+    let mut z = (seed ^ v).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// `raw` 为 `base` 处设备树中的属性值
+fn wipe(base: NonNull<u8>, raw: &[u8]) {
+    let offset = raw.as_ptr() as usize - base.as_ptr() as usize;
+    unsafe { base.add(offset).write_bytes(0, raw.len()) };
+}
+
+fn rndr() -> Option<u64> {
+    if !ID_AA64ISAR0_EL1.matches_all(ID_AA64ISAR0_EL1::RNDR::Supported) {
+        return None;
+    }
+    let v: u64;
+    let ok: u64;
+    unsafe {
+        asm!(
+            "mrs {v}, s3_3_c2_c4_0",
+            "cset {ok}, ne",
+            v = out(reg) v,
+            ok = out(reg) ok,
+        );
+    }
+    (ok != 0).then_some(v)
+}
@@ -17,8 +17,10 @@ mod debug;
 pub mod def;
 mod el1;
 mod el2;
+mod kaslr;
 mod lang_items;
 mod mmu;
+mod modules;
 mod paging;
 mod ram;
 mod reg;
@@ -28,11 +30,10 @@ mod trap;
 
 use aarch64_cpu::{asm::barrier, registers::*};
 
-use crate::mmu::set_page_size;
 use def::EarlyBootArgs;
 use fdt_parser::Fdt;
 use mmu::enable_mmu;
-use pie_boot_if::BootInfo;
+use pie_boot_if::{BOOT_MAGIC, BootInfo};
 use staticcell::*;
 
 pub(crate) static RETURN: StaticCell<BootInfo> = StaticCell::new(BootInfo::new());
@@ -42,7 +43,7 @@ static mut OFFSET: usize = 0;
 #[unsafe(no_mangle)]
 #[unsafe(naked)]
 #[unsafe(link_section = ".text.init")]
-unsafe extern "C" fn _start(_args: &EarlyBootArgs) -> ! {
+unsafe extern "C" fn _start(_args: &mut EarlyBootArgs) -> ! {
     naked_asm!(
         "
         mov   x19, x0
@@ -86,7 +87,7 @@ fn switch_to_target_el(bootargs: &EarlyBootArgs) {
     }
 }
 
-fn entry(bootargs: &EarlyBootArgs) -> *mut () {
+fn entry(bootargs: &mut EarlyBootArgs) -> *mut () {
     enable_fp();
     unsafe {
         clean_bss();
@@ -96,16 +97,29 @@ fn entry(bootargs: &EarlyBootArgs) -> *mut () {
         cache::dcache_all(cache::DcacheOp::CleanAndInvalidate);
 
         let mut fdt = bootargs.args[0];
-        OFFSET = bootargs.kimage_addr_vma as usize - bootargs.kimage_addr_lma as usize;
-        set_page_size(bootargs.page_size);
+        let layout = mmu::init_layout(bootargs);
         ram::init(bootargs.kcode_end as _);
+        let modules = modules::find(fdt as _);
+        for m in &modules {
+            ram::protect(m.start..m.end);
+        }
 
         if bootargs.debug() {
-            debug::fdt::init_debugcon(fdt as _, bootargs.kliner_offset);
+            debug::fdt::init_debugcon(fdt as _, layout.kliner_offset);
         }
 
         printkv!("fdt", "{fdt:#x}");
 
+        if bootargs.magic != BOOT_MAGIC {
+            println!(
+                "Kernel boot magic {:#x} != {BOOT_MAGIC:#x}, kernel and loader versions mismatch",
+                bootargs.magic
+            );
+            loop {
+                aarch64_cpu::asm::wfe();
+            }
+        }
+
         trap::setup();
 
         asm!("msr daifset, #2");
@@ -113,10 +127,25 @@ fn entry(bootargs: &EarlyBootArgs) -> *mut () {
 
         fdt = save_fdt(fdt as _);
 
+        let kaslr_offset = kaslr::offset(bootargs, &layout, fdt);
+        relocate::apply_kernel(bootargs, kaslr_offset);
+        bootargs.virt_entry = bootargs.virt_entry.byte_add(kaslr_offset);
+        bootargs.kimage_addr_vma = bootargs.kimage_addr_vma.byte_add(kaslr_offset);
+        bootargs.stack_top_vma = bootargs.stack_top_vma.byte_add(kaslr_offset);
+        OFFSET = bootargs.kimage_addr_vma as usize - bootargs.kimage_addr_lma as usize;
+        printkv!("KASLR offset", "{kaslr_offset:#x}");
+
         printkv!("EL", "{}", CurrentEL.read(CurrentEL::EL));
 
         printkv!("_start", "{:p}", bootargs.kimage_addr_vma);
         printkv!("stack", "{:p}", bootargs.stack_top_vma);
+        printkv!(
+            "VA bits",
+            "{} ({} levels)",
+            layout.va_bits,
+            layout.page_levels
+        );
+        printkv!("liner offset", "{:#x}", layout.kliner_offset);
 
         let loader_at = loader_at();
 
@@ -126,16 +155,20 @@ fn entry(bootargs: &EarlyBootArgs) -> *mut () {
             loader_at,
             loader_at.add(loader_size())
         );
-        enable_mmu(bootargs, fdt);
-        debug::relocate_uart(bootargs.kliner_offset);
+        enable_mmu(bootargs, &layout, fdt);
+        debug::relocate_uart(layout.kliner_offset);
         let ret = RETURN.as_mut();
 
+        ret.magic = BOOT_MAGIC;
         ret.fdt = NonNull::new(fdt as _);
         ret.cpu_id = MPIDR_EL1.get() as usize & 0xFFFFFF;
 
         ret.kimage_start_lma = bootargs.kimage_addr_lma as _;
         ret.kimage_start_vma = bootargs.kimage_addr_vma as _;
+        ret.kaslr_offset = kaslr_offset;
 
+        ret.modules = modules;
+        ret.cmdline = modules::bootargs(fdt as _);
         ret.memory_regions = ram::memory_regions().into();
         ret.free_memory_start = ram::current();
         cache::flush_dcache_range(_stext as usize, _end as usize - _stext as usize);
@@ -5,22 +5,39 @@ use crate::{
     *,
 };
 
+use core::ops::Range;
+
+use fdt_parser::Status;
 use num_align::{NumAlign, NumAssertAlign};
 use page_table_generic::Access;
+use pie_boot_if::{MemoryRegion, MemoryRegionKind, VaLayout, Vec, carve_usable};
+
+/// Most `no-map` reserved ranges kept out of the boot table
+const MAX_NO_MAP: usize = 32;
 
 static mut KLINER_OFFSET: usize = 0;
 static mut PAGE_SIZE: usize = 0;
 
-fn enable_mmu_el1(args: &EarlyBootArgs, fdt: usize) {
-    reg::el1::setup_table_regs();
-    let addr = new_boot_table::<el1::Table, _>(args, fdt, el1::Pte::new);
+fn enable_mmu_el1(args: &EarlyBootArgs, layout: &VaLayout, fdt: usize) {
+    reg::el1::setup_table_regs(layout.va_bits, layout.page_shift);
+    let addr = match layout.page_shift {
+        12 => new_boot_table::<el1::Table<12>, _>(args, layout, fdt, el1::Pte::new),
+        14 => new_boot_table::<el1::Table<14>, _>(args, layout, fdt, el1::Pte::new),
+        16 => new_boot_table::<el1::Table<16>, _>(args, layout, fdt, el1::Pte::new),
+        _ => panic!("Unsupported page size: {:#x}", layout.page_size()),
+    };
     reg::el1::set_table(addr.raw());
     reg::el1::setup_sctlr();
 }
 
-fn enable_mmu_el2(args: &EarlyBootArgs, fdt: usize) {
-    reg::el2::setup_table_regs();
-    let addr = new_boot_table::<el2::Table, _>(args, fdt, el2::Pte::new);
+fn enable_mmu_el2(args: &EarlyBootArgs, layout: &VaLayout, fdt: usize) {
+    reg::el2::setup_table_regs(layout.va_bits, layout.page_shift);
+    let addr = match layout.page_shift {
+        12 => new_boot_table::<el2::Table<12>, _>(args, layout, fdt, el2::Pte::new),
+        14 => new_boot_table::<el2::Table<14>, _>(args, layout, fdt, el2::Pte::new),
+        16 => new_boot_table::<el2::Table<16>, _>(args, layout, fdt, el2::Pte::new),
+        _ => panic!("Unsupported page size: {:#x}", layout.page_size()),
+    };
     reg::el2::set_table(addr.raw());
     reg::el2::setup_sctlr();
 }
@@ -29,30 +46,50 @@ fn kliner_offset() -> usize {
     unsafe { KLINER_OFFSET }
 }
 
-pub(crate) fn set_page_size(size: usize) {
+/// Widens the linked layout of the kernel to the virtual address width the
+/// CPU supports.
+pub(crate) fn init_layout(args: &EarlyBootArgs) -> VaLayout {
+    let linked = args.layout;
+    let cpu_va_bits = match reg::cpu_va_bits(linked.page_shift) {
+        Some(bits) => bits,
+        None => panic!("page size {:#x} not supported by CPU", linked.page_size()),
+    };
+    let va_bits = cpu_va_bits.min(args.va_bits_max);
+    if va_bits < linked.va_bits {
+        panic!(
+            "CPU supports {va_bits}-bit VA, kernel is linked for {}-bit",
+            linked.va_bits
+        );
+    }
+
+    let layout = linked.with_va_bits(va_bits);
     unsafe {
-        PAGE_SIZE = size;
+        KLINER_OFFSET = layout.kliner_offset;
+        PAGE_SIZE = layout.page_size();
     }
+    RETURN.as_mut().va_layout = layout;
+    layout
 }
 
 pub(crate) fn page_size() -> usize {
     unsafe { PAGE_SIZE }
 }
 
-pub fn enable_mmu(args: &EarlyBootArgs, fdt: usize) {
-    unsafe {
-        KLINER_OFFSET = args.kliner_offset;
-        PAGE_SIZE = args.page_size;
-    }
+pub fn enable_mmu(args: &EarlyBootArgs, layout: &VaLayout, fdt: usize) {
     match args.el {
-        1 => enable_mmu_el1(args, fdt),
-        2 => enable_mmu_el2(args, fdt),
+        1 => enable_mmu_el1(args, layout, fdt),
+        2 => enable_mmu_el2(args, layout, fdt),
         _ => panic!("Unsupported exception level: {}", args.el),
     }
 }
 
 /// `rsv_space` 在 `boot stack` 之后保留的空间到校
-pub fn new_boot_table<T, F>(args: &EarlyBootArgs, fdt: usize, new_pte: F) -> PhysAddr
+pub fn new_boot_table<T, F>(
+    args: &EarlyBootArgs,
+    layout: &VaLayout,
+    fdt: usize,
+    new_pte: F,
+) -> PhysAddr
 where
     T: TableGeneric,
     F: Fn(CacheKind) -> T::PTE + Copy,
@@ -67,7 +104,12 @@ where
 
     printkv!("BootTable space", "[{:p} --)", table_start);
 
-    let mut table = early_err!(PageTableRef::<'_, T>::create_empty(access));
+    let mut table = early_err!(PageTableRef::<'_, T>::new_with_level(
+        layout.page_levels,
+        access
+    ));
+    let fdt = early_err!(parse_fdt(fdt));
+    let no_map = early_err!(no_map_ranges(&fdt));
     unsafe {
         let align = if kcode_offset.is_aligned_to(GB) {
             GB
@@ -95,19 +137,28 @@ where
             code_start_phys + size
         );
 
-        early_err!(table.map(
-            MapConfig {
-                vaddr: code_start.into(),
-                paddr: code_start_phys.into(),
-                size,
-                pte: new_pte(CacheKind::Normal),
-                allow_huge: true,
-                flush: false,
-            },
-            access,
-        ));
+        // The window reaches past the image, keep `no-map` memory out of it as well
+        let window = MemoryRegion {
+            start: code_start_phys,
+            end: code_start_phys + size,
+            kind: MemoryRegionKind::Ram,
+        };
+        for part in carve_usable(core::slice::from_ref(&window), &no_map) {
+            early_err!(table.map(
+                MapConfig {
+                    vaddr: (part.start + kcode_offset).into(),
+                    paddr: part.start.into(),
+                    size: part.end - part.start,
+                    pte: new_pte(CacheKind::Normal),
+                    max_level: usize::MAX,
+                    min_level: 1,
+                    flush: false,
+                },
+                access,
+            ));
+        }
 
-        early_err!(add_rams(fdt, &mut table, access, new_pte));
+        early_err!(add_rams(&fdt, &no_map, &mut table, access, new_pte));
         if debug::reg_base() > 0 {
             let paddr = debug::reg_base();
             let vaddr = paddr + KLINER_OFFSET;
@@ -118,7 +169,8 @@ where
                     paddr: paddr.into(),
                     size: page_size(),
                     pte: new_pte(CacheKind::Device),
-                    allow_huge: true,
+                    max_level: usize::MAX,
+                    min_level: 1,
                     flush: false,
                 },
                 access,
@@ -141,7 +193,8 @@ where
                     paddr: start.into(),
                     size,
                     pte: new_pte(CacheKind::Normal),
-                    allow_huge: true,
+                    max_level: usize::MAX,
+                    min_level: 1,
                     flush: false,
                 },
                 access,
@@ -157,8 +210,14 @@ where
     table.paddr()
 }
 
+fn parse_fdt(fdt: usize) -> Result<Fdt<'static>, &'static str> {
+    let fdt = NonNull::new(fdt as _).ok_or("Invalid FDT pointer")?;
+    Fdt::from_ptr(fdt).map_err(|_| "Invalid FDT pointer")
+}
+
 fn add_rams<T, F>(
-    fdt: usize,
+    fdt: &Fdt<'static>,
+    no_map: &[Range<usize>],
     table: &mut PageTableRef<'_, T>,
     access: &mut impl Access,
     new_pte: F,
@@ -167,35 +226,82 @@ where
     T: TableGeneric,
     F: Fn(CacheKind) -> T::PTE,
 {
-    let fdt = match NonNull::new(fdt as _) {
-        Some(v) => v,
-        _ => {
-            return Err("Invalid FDT pointer");
-        }
-    };
-
-    let fdt: Fdt<'static> = Fdt::from_ptr(fdt).map_err(|_| "Invalid FDT pointer")?;
     for memory in fdt.memory().flat_map(|mem| mem.regions()) {
         if memory.size == 0 {
             continue; // Skip zero-sized regions
         }
-        let paddr = memory.address as usize;
-        let vaddr = paddr + kliner_offset();
-        printkv!("ram", "{:#x}-> {:#x}", vaddr, paddr);
-        unsafe {
-            early_err!(table.map(
-                MapConfig {
-                    vaddr: vaddr.into(),
-                    paddr: paddr.into(),
-                    size: memory.size,
-                    pte: new_pte(CacheKind::Normal),
-                    allow_huge: true,
-                    flush: false,
-                },
-                access,
-            ));
+        let bank = MemoryRegion {
+            start: memory.address as usize,
+            end: memory.address as usize + memory.size,
+            kind: MemoryRegionKind::Ram,
+        };
+        // `no-map` memory must not get a cacheable alias
+        for ram in carve_usable(core::slice::from_ref(&bank), no_map) {
+            map_ram(table, access, &new_pte, ram.start..ram.end)?;
         }
     }
 
     Ok(())
 }
+
+fn map_ram<T, F>(
+    table: &mut PageTableRef<'_, T>,
+    access: &mut impl Access,
+    new_pte: &F,
+    ram: Range<usize>,
+) -> Result<(), &'static str>
+where
+    T: TableGeneric,
+    F: Fn(CacheKind) -> T::PTE,
+{
+    let paddr = ram.start;
+    let vaddr = paddr + kliner_offset();
+    printkv!("ram", "{:#x}-> {:#x}", vaddr, paddr);
+    unsafe {
+        early_err!(table.map(
+            MapConfig {
+                vaddr: vaddr.into(),
+                paddr: paddr.into(),
+                size: ram.len(),
+                pte: new_pte(CacheKind::Normal),
+                max_level: usize::MAX,
+                min_level: 1,
+                flush: false,
+            },
+            access,
+        ));
+    }
+    Ok(())
+}
+
+/// `reg` of the enabled `/reserved-memory` children with `no-map`, widened to whole pages
+fn no_map_ranges(fdt: &Fdt<'static>) -> Result<Vec<Range<usize>, MAX_NO_MAP>, &'static str> {
+    let mut ranges = Vec::new();
+    let Some(parent) = fdt.reserved_memory().next() else {
+        return Ok(ranges);
+    };
+    let level = parent.level;
+    let children = fdt
+        .all_nodes()
+        .skip_while(|n| n.name() != parent.name())
+        .skip(1)
+        .take_while(|n| n.level > level)
+        .filter(|n| n.level == level + 1)
+        .filter(|n| !matches!(n.status(), Some(Status::Disabled)))
+        .filter(|n| n.find_property("no-map").is_some());
+
+    for node in children {
+        for reg in node.reg().into_iter().flatten() {
+            let Some(size) = reg.size else {
+                continue;
+            };
+            let start = (reg.address as usize).align_down(page_size());
+            let end = (reg.address as usize + size).align_up(page_size());
+            printkv!("no-map", "[{start:#x}, {end:#x})");
+            ranges
+                .push(start..end)
+                .map_err(|_| "Too many no-map reserved memory ranges")?;
+        }
+    }
+    Ok(ranges)
+}
@@ -0,0 +1,101 @@
+//! `/chosen` 中引导程序传来的信息：模块（`linux,initrd-*`、`module@*`）和 `bootargs`
+
+use core::ptr::NonNull;
+
+use fdt_parser::{Fdt, Node, Property};
+use pie_boot_if::{BootModule, CMDLINE_CAPACITY, MAX_BOOT_MODULES, String, Vec};
+
+fn read_addr(prop: Property<'_>) -> usize {
+    if prop.raw_value().len() >= 8 {
+        prop.u64() as usize
+    } else {
+        prop.u32() as usize
+    }
+}
+
+fn push(modules: &mut Vec<BootModule, MAX_BOOT_MODULES>, name: &str, start: usize, end: usize) {
+    if start >= end {
+        return;
+    }
+    let mut module = BootModule {
+        name: String::new(),
+        start,
+        end,
+    };
+    for c in name.chars() {
+        if module.name.push(c).is_err() {
+            break;
+        }
+    }
+    printkv!(name, "[{start:#x}, {end:#x})");
+    if modules.push(module).is_err() {
+        printkv!("warning", "too many modules, `{name}` not protected");
+    }
+}
+
+fn chosen_children<'a>(fdt: &Fdt<'a>, chosen: &Node<'a>) -> impl Iterator<Item = Node<'a>> {
+    let level = chosen.level;
+    fdt.all_nodes()
+        .skip_while(|n| n.name() != "chosen")
+        .skip(1)
+        .take_while(move |n| n.level > level)
+        .filter(move |n| n.level == level + 1)
+}
+
+/// 从 FDT 读取模块
+pub fn find(fdt: *mut u8) -> Vec<BootModule, MAX_BOOT_MODULES> {
+    let mut modules = Vec::new();
+    let Some(fdt) = NonNull::new(fdt).and_then(|p| Fdt::from_ptr(p).ok()) else {
+        return modules;
+    };
+    let Some(chosen) = fdt.find_nodes("/chosen").next() else {
+        return modules;
+    };
+
+    if let (Some(start), Some(end)) = (
+        chosen.find_property("linux,initrd-start"),
+        chosen.find_property("linux,initrd-end"),
+    ) {
+        push(
+            &mut modules,
+            BootModule::INITRD,
+            read_addr(start),
+            read_addr(end),
+        );
+    }
+
+    for node in chosen_children(&fdt, &chosen).filter(|n| n.name().starts_with("module@")) {
+        for reg in node.reg().into_iter().flatten() {
+            let start = reg.address as usize;
+            push(
+                &mut modules,
+                node.name(),
+                start,
+                start + reg.size.unwrap_or(0),
+            );
+        }
+    }
+    modules
+}
+
+/// 读取 `/chosen/bootargs`，过长时截断
+pub fn bootargs(fdt: *mut u8) -> String<CMDLINE_CAPACITY> {
+    let mut cmdline = String::new();
+    let Some(fdt) = NonNull::new(fdt).and_then(|p| Fdt::from_ptr(p).ok()) else {
+        return cmdline;
+    };
+    let Some(args) = fdt.chosen().and_then(|c| c.bootargs()) else {
+        return cmdline;
+    };
+    for c in args.chars() {
+        if cmdline.push(c).is_err() {
+            printkv!(
+                "warning",
+                "bootargs longer than {CMDLINE_CAPACITY}, truncated"
+            );
+            break;
+        }
+    }
+    printkv!("bootargs", "{cmdline}");
+    cmdline
+}
@@ -1 +1,24 @@
 pub use page_table_generic::*;
+
+/// VMSAv8-64 translation granule parameters, by `PAGE_SHIFT` (12, 14 or 16).
+pub mod granule {
+    /// Output address bits `[PAGE_SHIFT, 48)` of a descriptor.
+    pub const fn phys_addr_mask(page_shift: usize) -> usize {
+        ((1 << 48) - 1) & !((1 << page_shift) - 1)
+    }
+
+    /// 52-bit VA without LPA2 is only available with 64K pages.
+    pub const fn valid_bits(page_shift: usize) -> usize {
+        if page_shift == 16 { 52 } else { 48 }
+    }
+
+    pub const fn levels(page_shift: usize) -> usize {
+        (valid_bits(page_shift) - page_shift).div_ceil(page_shift - 3)
+    }
+
+    /// 4K: 1G blocks at level 1. 16K/64K: level 1 blocks need a 52-bit
+    /// output address, so only level 2 blocks (32M/512M) are used.
+    pub const fn max_block_level(page_shift: usize) -> usize {
+        if page_shift == 12 { 3 } else { 2 }
+    }
+}
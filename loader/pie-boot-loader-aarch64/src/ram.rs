@@ -1,15 +1,17 @@
-use core::{alloc::Layout, cell::UnsafeCell};
+use core::{alloc::Layout, cell::UnsafeCell, ops::Range};
 
 use crate::{
     OFFSET,
     paging::{Access, PhysAddr},
 };
 use num_align::NumAlign;
-use pie_boot_if::{MemoryRegion, MemoryRegionKind};
+use pie_boot_if::{MAX_BOOT_MODULES, MemoryRegion, MemoryRegionKind, Vec};
 
 struct SimpleAllocator {
     start: usize,
     current: usize, // 当前分配位置
+    /// 不能分配的范围，如 initrd
+    protected: Vec<Range<usize>, MAX_BOOT_MODULES>,
 }
 
 impl SimpleAllocator {
@@ -17,6 +19,7 @@ impl SimpleAllocator {
         SimpleAllocator {
             start: 0,
             current: 0,
+            protected: Vec::new(),
         }
     }
 
@@ -26,11 +29,21 @@ impl SimpleAllocator {
     }
 
     pub fn alloc(&mut self, layout: Layout) -> *mut u8 {
-        unsafe {
-            let start = self.current.align_up(layout.align()) as *mut u8;
-            let end = start.add(layout.size());
-            self.current = end as usize;
-            start
+        loop {
+            let start = self.current.align_up(layout.align());
+            let end = start + layout.size();
+            // 跳过受保护的范围
+            match self
+                .protected
+                .iter()
+                .find(|r| r.start < end && r.end > start)
+            {
+                Some(r) => self.current = r.end,
+                None => {
+                    self.current = end;
+                    return start as *mut u8;
+                }
+            }
         }
     }
 }
@@ -67,6 +80,13 @@ impl Access for Ram {
     }
 }
 
+/// 之后的分配跳过 `range`，`Bootloader` 区域仍会覆盖它
+pub fn protect(range: Range<usize>) {
+    unsafe {
+        let _ = (*RAM_ALLOC.0.get()).protected.push(range);
+    }
+}
+
 pub fn init(kernel_end: usize) {
     unsafe {
         (*RAM_ALLOC.0.get()).init(kernel_end);
@@ -16,8 +16,10 @@ pub fn setup_sctlr() {
     barrier::isb(barrier::SY);
 }
 
+/// `va_bits` is the width of both the TTBR0 and TTBR1 address ranges,
+/// `page_shift` selects the translation granule.
 #[inline(always)]
-pub fn setup_table_regs() {
+pub fn setup_table_regs(va_bits: usize, page_shift: usize) {
     // Device-nGnRE
     let attr0 = MAIR_EL1::Attr0_Device::nonGathering_nonReordering_EarlyWriteAck;
     // Normal
@@ -29,26 +31,32 @@ pub fn setup_table_regs() {
     // WriteThrough
     let attr3 = MAIR_EL1::Attr3_Normal_Inner::WriteThrough_Transient_WriteAlloc
         + MAIR_EL1::Attr3_Normal_Outer::WriteThrough_Transient_WriteAlloc;
+    // Device-nGnRnE
+    let attr4 = MAIR_EL1::Attr4_Device::nonGathering_nonReordering_noEarlyWriteAck;
 
-    MAIR_EL1.write(attr0 + attr1 + attr2 + attr3);
+    MAIR_EL1.write(attr0 + attr1 + attr2 + attr3 + attr4);
 
-    // Enable TTBR0 and TTBR1 walks, page size = 4K, vaddr size = 48 bits, paddr size = 40 bits.
-    const VADDR_SIZE: u64 = 48;
-    const T0SZ: u64 = 64 - VADDR_SIZE;
+    // Enable TTBR0 and TTBR1 walks, paddr size from `ID_AA64MMFR0_EL1`.
+    let t0sz = 64 - va_bits as u64;
+    let (tg0, tg1) = match page_shift {
+        14 => (TCR_EL1::TG0::KiB_16, TCR_EL1::TG1::KiB_16),
+        16 => (TCR_EL1::TG0::KiB_64, TCR_EL1::TG1::KiB_64),
+        _ => (TCR_EL1::TG0::KiB_4, TCR_EL1::TG1::KiB_4),
+    };
 
     let tcr_flags0 = TCR_EL1::EPD0::EnableTTBR0Walks
-        + TCR_EL1::TG0::KiB_4
+        + tg0
         + TCR_EL1::SH0::Inner
         + TCR_EL1::ORGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
         + TCR_EL1::IRGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
-        + TCR_EL1::T0SZ.val(T0SZ);
+        + TCR_EL1::T0SZ.val(t0sz);
     let tcr_flags1 = TCR_EL1::EPD1::EnableTTBR1Walks
-        + TCR_EL1::TG1::KiB_4
+        + tg1
         + TCR_EL1::SH1::Inner
         + TCR_EL1::ORGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
         + TCR_EL1::IRGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
-        + TCR_EL1::T1SZ.val(T0SZ);
-    TCR_EL1.write(TCR_EL1::IPS::Bits_48 + tcr_flags0 + tcr_flags1);
+        + TCR_EL1::T1SZ.val(t0sz);
+    TCR_EL1.write(TCR_EL1::IPS.val(super::pa_range()) + tcr_flags0 + tcr_flags1);
 
     tlbi(VMALLE1);
     barrier::dsb(barrier::SY);
@@ -1,6 +1,8 @@
 use aarch64_cpu::{asm::barrier, registers::*};
 
-pub fn setup_table_regs() {
+/// `va_bits` is the width of the TTBR0 address range, `page_shift` selects
+/// the translation granule.
+pub fn setup_table_regs(va_bits: usize, page_shift: usize) {
     // Set EL1 to 64bit.
     // Enable `IMO` and `FMO` to make sure that:
     // * Physical IRQ interrupts are taken to EL2;
@@ -15,7 +17,7 @@ pub fn setup_table_regs() {
             + HCR_EL2::TSC::EnableTrapEl1SmcToEl2,
     );
 
-    // Device-nGnRnE
+    // Device-nGnRE
     let attr0 = MAIR_EL2::Attr0_Device::nonGathering_nonReordering_EarlyWriteAck;
     // Normal
     let attr1 = MAIR_EL2::Attr1_Normal_Inner::WriteBack_NonTransient_ReadWriteAlloc
@@ -26,20 +28,25 @@ pub fn setup_table_regs() {
     // WriteThrough
     let attr3 = MAIR_EL2::Attr3_Normal_Inner::WriteThrough_Transient_WriteAlloc
         + MAIR_EL2::Attr3_Normal_Outer::WriteThrough_Transient_WriteAlloc;
+    // Device-nGnRnE
+    let attr4 = MAIR_EL2::Attr4_Device::nonGathering_nonReordering_noEarlyWriteAck;
 
-    MAIR_EL2.write(attr0 + attr1 + attr2 + attr3);
+    MAIR_EL2.write(attr0 + attr1 + attr2 + attr3 + attr4);
 
-    // Enable TTBR0 and TTBR1 walks, page size = 4K, vaddr size = 48 bits, paddr size = 40 bits.
-    const VADDR_SIZE: u64 = 48;
-    const T0SZ: u64 = 64 - VADDR_SIZE;
+    // Paddr size from `ID_AA64MMFR0_EL1`.
+    let t0sz = 64 - va_bits as u64;
+    let tg0 = match page_shift {
+        14 => TCR_EL2::TG0::KiB_16,
+        16 => TCR_EL2::TG0::KiB_64,
+        _ => TCR_EL2::TG0::KiB_4,
+    };
 
-    // Enable TTBR0 and TTBR1 walks, page size = 4K, vaddr size = 48 bits, paddr size = 40 bits.
-    let tcr_flags0 = TCR_EL2::TG0::KiB_4
+    let tcr_flags0 = tg0
         + TCR_EL2::SH0::Inner
         + TCR_EL2::ORGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
         + TCR_EL2::IRGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
-        + TCR_EL2::T0SZ.val(T0SZ);
-    TCR_EL2.write(TCR_EL2::PS::Bits_40 + tcr_flags0);
+        + TCR_EL2::T0SZ.val(t0sz);
+    TCR_EL2.write(TCR_EL2::PS.val(super::pa_range()) + tcr_flags0);
     barrier::isb(barrier::SY);
 }
 
@@ -1,2 +1,33 @@
+use aarch64_cpu::registers::*;
+
 pub mod el1;
 pub mod el2;
+
+/// The widest virtual address the CPU translates with `1 << page_shift` pages,
+/// `None` if the granule is not implemented.
+///
+/// 52-bit addresses with 4K/16K pages need the LPA2 descriptor format, which
+/// is not supported, so only 64K pages go beyond 48 bits.
+pub fn cpu_va_bits(page_shift: usize) -> Option<usize> {
+    let supported = match page_shift {
+        12 => !ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran4::NotSupported),
+        14 => !ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran16::NotSupported),
+        16 => !ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran64::NotSupported),
+        _ => false,
+    };
+    if !supported {
+        return None;
+    }
+    if page_shift == 16 && ID_AA64MMFR2_EL1.read(ID_AA64MMFR2_EL1::VARange) == 1 {
+        Some(52)
+    } else {
+        Some(48)
+    }
+}
+
+/// `ID_AA64MMFR0_EL1.PARange`, limited to 48 bits, in the encoding of
+/// `TCR_ELx.{IPS, PS}`.
+#[inline(always)]
+fn pa_range() -> u64 {
+    ID_AA64MMFR0_EL1.read(ID_AA64MMFR0_EL1::PARange).min(0b101)
+}
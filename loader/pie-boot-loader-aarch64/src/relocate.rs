@@ -20,6 +20,8 @@ unsafe extern "C" {
     fn __rela_dyn_end();
 }
 
+use crate::def::EarlyBootArgs;
+
 // AArch64 重定位类型常量
 const R_AARCH64_RELATIVE: u32 = 1027;
 /// 计算加载偏移量 (实际地址 - 链接地址)
@@ -44,3 +46,26 @@ pub fn apply() {
         }
     }
 }
+
+/// 应用内核镜像的 .rela.dyn 重定位
+///
+/// 内核运行在物理地址时调用，`kaslr_offset` 为内核实际虚拟地址相对链接地址的偏移。
+pub fn apply_kernel(args: &EarlyBootArgs, kaslr_offset: usize) {
+    let link_base = args.kimage_addr_vma as usize;
+    let load_base = args.kimage_addr_lma as usize;
+
+    let start = args.rela_start as *const Elf64Rela;
+    let end = args.rela_end as *const Elf64Rela;
+    let num_entries = (end as usize - start as usize) / size_of::<Elf64Rela>();
+    let relocations = unsafe { core::slice::from_raw_parts(start, num_entries) };
+
+    for reloc in relocations {
+        match reloc.r_type_raw() {
+            R_AARCH64_RELATIVE => {
+                let addr = (reloc.r_offset as usize - link_base + load_base) as *mut usize;
+                unsafe { addr.write((reloc.r_addend as usize).wrapping_add(kaslr_offset)) };
+            }
+            ty => panic!("Unsupported kernel relocation type: {ty}"),
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{Ident, ItemFn, parse::Parse, parse_macro_input};
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{Ident, ItemFn, ItemStatic, LitStr, StaticMutability, parse::Parse, parse_macro_input};
 
 mod entry;
 
@@ -132,3 +133,85 @@ pub fn irq_handler(_args: TokenStream, input: TokenStream) -> TokenStream {
     }
     .into()
 }
+
+/// Register a handler for a kernel command line parameter.
+///
+/// Handlers run during boot, after the debug console is up and before the
+/// `#[entry]` function, once for every occurrence of the parameter. The
+/// argument is `None` for a bare flag.
+///
+/// # Examples
+///
+/// ``` ignore
+/// #[somehal::early_param("quiet")]
+/// fn quiet(_value: Option<&str>) {
+///     /* .. */
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn early_param(args: TokenStream, input: TokenStream) -> TokenStream {
+    let name = parse_macro_input!(args as LitStr);
+    let f = parse_macro_input!(input as ItemFn);
+    let ident = &f.sig.ident;
+    let item = format_ident!("__EARLY_PARAM_{}", ident.to_string().to_uppercase());
+
+    quote!(
+        #f
+
+        #[used]
+        #[unsafe(link_section = ".early_param")]
+        static #item: ::somehal::cmdline::EarlyParam = ::somehal::cmdline::EarlyParam {
+            name: #name,
+            handler: #ident,
+        };
+    )
+    .into()
+}
+
+/// Declare a per-CPU static.
+///
+/// The static is placed in the `.percpu` section and every CPU gets its own
+/// copy at boot. Its type becomes `somehal::percpu::PerCpu<T>`, use
+/// `this_cpu()` or `per_cpu(cpu_id)` to reach a copy. `static mut` is not
+/// allowed, use interior mutability instead.
+///
+/// # Examples
+///
+/// ``` ignore
+/// #[somehal::percpu]
+/// static IRQ_COUNT: AtomicUsize = AtomicUsize::new(0);
+///
+/// IRQ_COUNT.this_cpu().fetch_add(1, Ordering::Relaxed);
+/// ```
+#[proc_macro_attribute]
+pub fn percpu(args: TokenStream, input: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        return syn::Error::new(Span::call_site(), "This attribute accepts no arguments")
+            .to_compile_error()
+            .into();
+    }
+    let item = parse_macro_input!(input as ItemStatic);
+    if !matches!(item.mutability, StaticMutability::None) {
+        return syn::Error::new_spanned(
+            &item.mutability,
+            "`#[percpu]` statics can't be `mut`, use interior mutability",
+        )
+        .to_compile_error()
+        .into();
+    }
+    let ItemStatic {
+        attrs,
+        vis,
+        ident,
+        ty,
+        expr,
+        ..
+    } = item;
+
+    quote!(
+        #(#attrs)*
+        #[unsafe(link_section = ".percpu")]
+        #vis static #ident: ::somehal::percpu::PerCpu<#ty> = ::somehal::percpu::PerCpu::new(#expr);
+    )
+    .into()
+}
@@ -59,17 +59,69 @@ pub trait Access {
 
 use thiserror::Error;
 
+/// The page table operation during which a [`PagingError`] occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PagingOp {
+    Map,
+    Unmap,
+}
+
+impl core::fmt::Display for PagingOp {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PagingOp::Map => write!(f, "map"),
+            PagingOp::Unmap => write!(f, "unmap"),
+        }
+    }
+}
+
 /// The error type for page table operation failures.
-#[derive(Error, Debug, PartialEq, Eq)]
+///
+/// Every variant except [`PagingError::NoMemory`] carries the operation and the
+/// addresses being processed when the failure happened.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PagingError {
     #[error("can't allocate memory")]
     NoMemory,
-    #[error("{0} is not aligned")]
-    NotAligned(&'static str),
-    #[error("not mapped")]
-    NotMapped,
-    #[error("already mapped")]
-    AlreadyMapped,
+    #[error("{op}: {field} is not aligned to {align:#x} (vaddr {vaddr:?}, paddr {paddr:?})")]
+    NotAligned {
+        op: PagingOp,
+        /// Which argument is misaligned, `vaddr`, `paddr` or `size`.
+        field: &'static str,
+        vaddr: VirtAddr,
+        paddr: PhysAddr,
+        align: usize,
+    },
+    #[error("{op}: vaddr {vaddr:?} is not mapped")]
+    NotMapped { op: PagingOp, vaddr: VirtAddr },
+    #[error("{op}: vaddr {vaddr:?} is already mapped at level {level}")]
+    AlreadyMapped {
+        op: PagingOp,
+        vaddr: VirtAddr,
+        paddr: PhysAddr,
+        level: usize,
+    },
+    #[error("{op}: invalid level {level} for vaddr {vaddr:?} (paddr {paddr:?})")]
+    InvalidLevel {
+        op: PagingOp,
+        vaddr: VirtAddr,
+        paddr: PhysAddr,
+        level: usize,
+    },
+    #[error("{op}: vaddr {vaddr:?} is covered by a level {level} block (paddr {paddr:?})")]
+    BlockConflict {
+        op: PagingOp,
+        vaddr: VirtAddr,
+        paddr: PhysAddr,
+        level: usize,
+    },
+    #[error("{op}: [{vaddr:?}, +{size:#x}) is out of the {valid_bits}-bit address range")]
+    OutOfRange {
+        op: PagingOp,
+        vaddr: VirtAddr,
+        size: usize,
+        valid_bits: usize,
+    },
 }
 
 /// The specialized `Result` type for page table operations.
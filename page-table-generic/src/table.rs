@@ -7,8 +7,8 @@ use core::{
 use num_align::*;
 
 use super::{
-    Access, PTEGeneric, PTEInfo, PagingError, PagingResult, PhysAddr, TableGeneric, VirtAddr,
-    iter::TableIter,
+    Access, PTEGeneric, PTEInfo, PagingError, PagingOp, PagingResult, PhysAddr, TableGeneric,
+    VirtAddr, iter::TableIter,
 };
 
 #[repr(C)]
@@ -18,11 +18,18 @@ pub struct MapConfig<P: PTEGeneric> {
     pub paddr: PhysAddr,
     pub size: usize,
     pub pte: P,
-    pub allow_huge: bool,
+    /// The highest level a leaf entry may be placed at, `1` means base pages
+    /// only. It is clamped to the table's `MAX_BLOCK_LEVEL`.
+    pub max_level: usize,
+    /// The lowest level a leaf entry may be placed at. Mapping fails instead
+    /// of falling back to smaller pages when this can't be met.
+    pub min_level: usize,
     pub flush: bool,
 }
 
 impl<P: PTEGeneric> MapConfig<P> {
+    /// When `allow_huge` is true, the mapping may use any block size the table
+    /// supports, otherwise it only uses base pages.
     pub fn new(
         vaddr: VirtAddr,
         paddr: PhysAddr,
@@ -36,10 +43,40 @@ impl<P: PTEGeneric> MapConfig<P> {
             paddr,
             size,
             pte,
-            allow_huge,
+            max_level: if allow_huge { usize::MAX } else { 1 },
+            min_level: 1,
             flush,
         }
     }
+
+    /// Limits leaf entries to levels `min_level..=max_level`.
+    pub fn with_levels(mut self, min_level: usize, max_level: usize) -> Self {
+        self.min_level = min_level;
+        self.max_level = max_level;
+        self
+    }
+
+    /// Limits leaf entries of table `T` to sizes in `min_size..=max_size`.
+    pub fn with_page_sizes<T: TableGeneric<PTE = P>>(
+        mut self,
+        min_size: usize,
+        max_size: usize,
+    ) -> Self {
+        let size_of = |level| PageWalk::<T>::new(level).level_entry_size();
+        self.min_level = (1..=T::LEVEL)
+            .find(|&level| size_of(level) >= min_size)
+            .unwrap_or(T::LEVEL + 1);
+        self.max_level = (1..=T::LEVEL)
+            .rev()
+            .find(|&level| size_of(level) <= max_size)
+            .unwrap_or(0);
+        self
+    }
+
+    /// Maps with leaf entries of table `T` of exactly `page_size` bytes.
+    pub fn with_page_size<T: TableGeneric<PTE = P>>(self, page_size: usize) -> Self {
+        self.with_page_sizes::<T>(page_size, page_size)
+    }
 }
 
 #[repr(C)]
@@ -100,11 +137,20 @@ impl<'a, T: TableGeneric> PageTableRef<'a, T> {
     /// The virtual and physical memory regions start with `vaddr` and `paddr`
     /// respectively. The region size is `size`. The addresses and `size` must
     /// be aligned to 4K, otherwise it will return [`Err(PagingError::NotAligned)`].
+    /// The region must fit in the [`valid_bits`](Self::valid_bits) address window of the table,
+    /// otherwise it will return [`Err(PagingError::OutOfRange)`].
     ///
-    /// When `allow_huge` is true, it will try to map the region with huge pages
-    /// if possible. Otherwise, it will map the region with 4K pages.
+    /// Each entry uses the largest level in `min_level..=max_level` that the
+    /// addresses and remaining size allow. When `min_level` is above 1, the
+    /// addresses and `size` must be aligned to that level's entry size, and
+    /// an empty level range returns [`Err(PagingError::InvalidLevel)`].
+    /// An entry that is already valid is never overwritten, mapping over it
+    /// returns [`Err(PagingError::AlreadyMapped)`].
     ///
     /// [`Err(PagingError::NotAligned)`]: PagingError::NotAligned
+    /// [`Err(PagingError::OutOfRange)`]: PagingError::OutOfRange
+    /// [`Err(PagingError::InvalidLevel)`]: PagingError::InvalidLevel
+    /// [`Err(PagingError::AlreadyMapped)`]: PagingError::AlreadyMapped
     ///
     /// # Safety
     /// User must ensure that the physical address is valid.
@@ -115,16 +161,37 @@ impl<'a, T: TableGeneric> PageTableRef<'a, T> {
     ) -> PagingResult {
         let vaddr = config.vaddr;
         let paddr = config.paddr;
+        let mut size = config.size;
 
-        if !vaddr.raw().is_aligned_to(T::PAGE_SIZE) {
-            return Err(PagingError::NotAligned("vaddr"));
+        let min_level = config.min_level;
+        let max_level = config.max_level.min(T::MAX_BLOCK_LEVEL).min(self.level());
+        if min_level == 0 || min_level > max_level {
+            return Err(PagingError::InvalidLevel {
+                op: PagingOp::Map,
+                vaddr,
+                paddr,
+                level: min_level,
+            });
         }
 
-        if !paddr.raw().is_aligned_to(T::PAGE_SIZE) {
-            return Err(PagingError::NotAligned("paddr"));
+        let align = self.walk.copy_with_level(min_level).level_entry_size();
+        for (field, value) in [
+            ("vaddr", vaddr.raw()),
+            ("paddr", paddr.raw()),
+            ("size", size),
+        ] {
+            if !value.is_aligned_to(align) {
+                return Err(PagingError::NotAligned {
+                    op: PagingOp::Map,
+                    field,
+                    vaddr,
+                    paddr,
+                    align,
+                });
+            }
         }
 
-        let mut size = config.size;
+        self.check_range(PagingOp::Map, vaddr, size)?;
 
         let mut map_cfg = _MapConfig {
             vaddr,
@@ -133,19 +200,15 @@ impl<'a, T: TableGeneric> PageTableRef<'a, T> {
         };
 
         while size > 0 {
-            let level_deepth = if config.allow_huge {
-                let v_align = self.walk.detect_align_level(map_cfg.vaddr.raw(), size);
-                let p_align = self.walk.detect_align_level(map_cfg.paddr.raw(), size);
-                v_align.min(p_align).min(T::MAX_BLOCK_LEVEL)
-            } else {
-                1
-            };
+            let v_align = self.walk.detect_align_level(map_cfg.vaddr.raw(), size);
+            let p_align = self.walk.detect_align_level(map_cfg.paddr.raw(), size);
+            let level_deepth = v_align.min(p_align).min(max_level);
             unsafe { self.get_entry_or_create(map_cfg, level_deepth, access)? };
 
             let map_size = self.walk.copy_with_level(level_deepth).level_entry_size();
 
             if config.flush {
-                T::flush(Some(vaddr));
+                T::flush(Some(map_cfg.vaddr));
             }
             map_cfg.vaddr += map_size;
             map_cfg.paddr += map_size;
@@ -154,6 +217,144 @@ impl<'a, T: TableGeneric> PageTableRef<'a, T> {
         Ok(())
     }
 
+    /// Removes the leaf entries covering `[vaddr, vaddr + size)`.
+    ///
+    /// Holes in the range are skipped. A block entry that is only partly
+    /// covered is not split, it returns [`Err(PagingError::BlockConflict)`].
+    /// Intermediate tables are kept, they are freed by [`release`](Self::release).
+    ///
+    /// [`Err(PagingError::BlockConflict)`]: PagingError::BlockConflict
+    ///
+    /// # Safety
+    /// User must ensure that nothing accesses the range anymore.
+    pub unsafe fn unmap(
+        &mut self,
+        vaddr: VirtAddr,
+        size: usize,
+        flush: bool,
+        access: &mut impl Access,
+    ) -> PagingResult {
+        let align = T::PAGE_SIZE;
+        for (field, value) in [("vaddr", vaddr.raw()), ("size", size)] {
+            if !value.is_aligned_to(align) {
+                return Err(PagingError::NotAligned {
+                    op: PagingOp::Unmap,
+                    field,
+                    vaddr,
+                    paddr: 0usize.into(),
+                    align,
+                });
+            }
+        }
+
+        self.check_range(PagingOp::Unmap, vaddr, size)?;
+
+        let mut vaddr = vaddr;
+        let mut size = size;
+        while size > 0 {
+            let step = self.unmap_entry(vaddr, size, flush, access)?;
+            if step >= size {
+                break;
+            }
+            vaddr += step;
+            size -= step;
+        }
+        Ok(())
+    }
+
+    /// Clears the entry mapping `vaddr`, returns the size of the range it
+    /// covered, or the distance to the next entry if nothing is mapped.
+    fn unmap_entry(
+        &mut self,
+        vaddr: VirtAddr,
+        size: usize,
+        flush: bool,
+        access: &impl Access,
+    ) -> PagingResult<usize> {
+        let mut table = *self;
+        loop {
+            let idx = table.index_of_table(vaddr);
+            let entry_size = table.entry_size();
+            let pte = table.get_pte(idx, access);
+
+            if !pte.valid() {
+                return Ok(entry_size - (vaddr.raw() & (entry_size - 1)));
+            }
+
+            if table.level() == 1 || pte.is_huge() {
+                if !vaddr.raw().is_aligned_to(entry_size) || size < entry_size {
+                    return Err(PagingError::BlockConflict {
+                        op: PagingOp::Unmap,
+                        vaddr,
+                        paddr: pte.paddr(),
+                        level: table.level(),
+                    });
+                }
+                let mut pte = pte;
+                pte.set_valid(false);
+                table.as_slice_mut(access)[idx] = pte;
+                if flush {
+                    T::flush(Some(vaddr));
+                }
+                return Ok(entry_size);
+            }
+
+            table = Self::from_addr(pte.paddr(), table.level() - 1);
+        }
+    }
+
+    /// Checks that `[vaddr, vaddr + size)` lies in the window this table
+    /// translates: the bits above [`valid_bits`](Self::valid_bits) must be all
+    /// zeros or all ones, and the range must not run past the end of the window.
+    fn check_range(&self, op: PagingOp, vaddr: VirtAddr, size: usize) -> PagingResult {
+        let valid_bits = self.valid_bits();
+        if valid_bits >= usize::BITS as usize {
+            return Ok(());
+        }
+        let err = PagingError::OutOfRange {
+            op,
+            vaddr,
+            size,
+            valid_bits,
+        };
+
+        let high = vaddr.raw() >> valid_bits;
+        if high != 0 && high != usize::MAX >> valid_bits {
+            return Err(err);
+        }
+
+        let offset = vaddr.raw() & ((1 << valid_bits) - 1);
+        match offset.checked_add(size) {
+            Some(end) if end <= 1 << valid_bits => Ok(()),
+            _ => Err(err),
+        }
+    }
+
+    /// Width of the virtual address window translated by this table.
+    ///
+    /// Limited by `T::VALID_BITS` and by the level of the table, so a root
+    /// created with fewer levels than `T::LEVEL` covers a smaller window.
+    pub fn valid_bits(&self) -> usize {
+        let bits = log2(self.entry_size()) + log2(T::TABLE_LEN);
+        bits.min(T::VALID_BITS)
+    }
+
+    /// Translates `vaddr` to the physical address it is mapped to, `None` if unmapped.
+    pub fn translate(&self, vaddr: VirtAddr, access: &impl Access) -> Option<PhysAddr> {
+        let mut table = *self;
+        loop {
+            let pte = table.get_pte(table.index_of_table(vaddr), access);
+            if !pte.valid() {
+                return None;
+            }
+            if pte.is_huge() || table.level() == 1 {
+                let offset = vaddr.raw() & (table.entry_size() - 1);
+                return Some((pte.paddr().raw() + offset).into());
+            }
+            table = Self::from_addr(pte.paddr(), table.level() - 1);
+        }
+    }
+
     pub fn iter_all<A: Access>(&self, access: &'a A) -> impl Iterator<Item = PTEInfo<T::PTE>> + 'a {
         TableIter::new(0 as _, *self, access)
     }
@@ -207,6 +408,15 @@ impl<'a, T: TableGeneric> PageTableRef<'a, T> {
         while table.level() > 0 {
             let idx = table.index_of_table(map_cfg.vaddr);
             if table.level() == level {
+                let old = table.get_pte(idx, access);
+                if old.valid() {
+                    return Err(PagingError::AlreadyMapped {
+                        op: PagingOp::Map,
+                        vaddr: map_cfg.vaddr,
+                        paddr: old.paddr(),
+                        level,
+                    });
+                }
                 let mut pte: <T as TableGeneric>::PTE = map_cfg.pte;
                 pte.set_paddr(map_cfg.paddr);
                 pte.set_valid(true);
@@ -220,7 +430,12 @@ impl<'a, T: TableGeneric> PageTableRef<'a, T> {
             }
             table = unsafe { table.sub_table_or_create(idx, map_cfg, access)? };
         }
-        Err(PagingError::NotAligned("vaddr"))
+        Err(PagingError::InvalidLevel {
+            op: PagingOp::Map,
+            vaddr: map_cfg.vaddr,
+            paddr: map_cfg.paddr,
+            level,
+        })
     }
 
     unsafe fn sub_table_or_create(
@@ -233,6 +448,14 @@ impl<'a, T: TableGeneric> PageTableRef<'a, T> {
         let sub_level = self.level() - 1;
 
         if pte.valid() {
+            if pte.is_huge() {
+                return Err(PagingError::BlockConflict {
+                    op: PagingOp::Map,
+                    vaddr: map_cfg.vaddr,
+                    paddr: pte.paddr(),
+                    level: self.level(),
+                });
+            }
             Ok(Self::from_addr(pte.paddr(), sub_level))
         } else {
             pte = map_cfg.pte;
@@ -362,7 +585,13 @@ impl<T: TableGeneric> PageWalk<T> {
     }
 
     fn index_of_table(&self, vaddr: VirtAddr) -> usize {
-        (vaddr.raw() >> self.level_entry_size_shift()) & (Self::table_len() - 1)
+        // 去掉符号扩展的高位，根页表可能不满 (如 16K 页 48 位只有 2 项)
+        let vaddr = if T::VALID_BITS < usize::BITS as usize {
+            vaddr.raw() & ((1 << T::VALID_BITS) - 1)
+        } else {
+            vaddr.raw()
+        };
+        (vaddr >> self.level_entry_size_shift()) & (Self::table_len() - 1)
     }
 
     fn level_entry_size(&self) -> usize {
@@ -425,7 +654,7 @@ mod test {
             todo!()
         }
 
-        fn set_paddr(&mut self, paddr: PhysAddr) {
+        fn set_paddr(&mut self, _paddr: PhysAddr) {
             todo!()
         }
     }
@@ -464,6 +693,30 @@ mod test {
         assert_eq!(w.index_of_table(0xffff_ffc0_8000_0000.into()), 0x102);
     }
 
+    #[derive(Clone, Copy)]
+    struct TestTable16K;
+    impl TableGeneric for TestTable16K {
+        type PTE = TestPTE;
+        const PAGE_SIZE: usize = 0x4000;
+        const VALID_BITS: usize = 48;
+
+        fn flush(_vaddr: Option<crate::VirtAddr>) {
+            todo!()
+        }
+    }
+
+    #[test]
+    fn test_idx_of_table_16k() {
+        let w = PageWalk::<TestTable16K>::new(4);
+        assert_eq!(w.level_entry_size(), 128 * 1024 * GB);
+        assert_eq!(w.index_of_table(0xffff_0000_0000_0000.into()), 0);
+        assert_eq!(w.index_of_table(0xffff_8000_0000_0000.into()), 1);
+
+        let w = PageWalk::<TestTable16K>::new(2);
+        assert_eq!(w.level_entry_size(), 32 * MB);
+        assert_eq!(w.index_of_table(0xffff_8000_0200_0000.into()), 1);
+    }
+
     #[test]
     fn test_detect_align() {
         let s = 4 * GB;
@@ -1,3 +1,5 @@
+#![allow(clippy::uninlined_format_args)]
+
 use std::{
     alloc::{self, Layout},
     fmt::Debug,
@@ -324,6 +326,304 @@ fn test_release() {
     pg.release(&mut access);
 }
 
+#[test]
+fn test_not_aligned() {
+    let (mut access, mut pg) = new_alloc_and_table();
+    let err = unsafe {
+        pg.map(
+            MapConfig::new(
+                0xffff000000000000usize.into(),
+                0x80001000usize.into(),
+                0x1800,
+                PteImpl(0),
+                false,
+                false,
+            ),
+            &mut access,
+        )
+    }
+    .unwrap_err();
+
+    assert_eq!(
+        err,
+        PagingError::NotAligned {
+            op: PagingOp::Map,
+            field: "size",
+            vaddr: 0xffff000000000000usize.into(),
+            paddr: 0x80001000usize.into(),
+            align: 0x1000,
+        }
+    );
+    println!("{err}");
+}
+
+#[test]
+fn test_out_of_range() {
+    let (mut access, mut pg) = new_alloc_and_table();
+    let err = unsafe {
+        pg.map(
+            MapConfig::new(
+                0x0001000000000000usize.into(),
+                0x0usize.into(),
+                0x1000,
+                PteImpl(0),
+                false,
+                false,
+            ),
+            &mut access,
+        )
+    }
+    .unwrap_err();
+    assert!(matches!(
+        err,
+        PagingError::OutOfRange { valid_bits: 48, .. }
+    ));
+
+    let err = unsafe {
+        pg.map(
+            MapConfig::new(
+                0x0000fffffffff000usize.into(),
+                0x0usize.into(),
+                0x2000,
+                PteImpl(0),
+                false,
+                false,
+            ),
+            &mut access,
+        )
+    }
+    .unwrap_err();
+    assert!(matches!(err, PagingError::OutOfRange { size: 0x2000, .. }));
+}
+
+#[test]
+fn test_out_of_range_level() {
+    let mut access = AccessImpl::new();
+    let mut pg = PageTableRef::<Table>::new_with_level(3, &mut access).unwrap();
+    assert_eq!(pg.valid_bits(), 39);
+
+    let err = unsafe {
+        pg.map(
+            MapConfig::new(
+                0xffff000000000000usize.into(),
+                0x0usize.into(),
+                0x1000,
+                PteImpl(0),
+                false,
+                false,
+            ),
+            &mut access,
+        )
+    }
+    .unwrap_err();
+    assert!(matches!(
+        err,
+        PagingError::OutOfRange { valid_bits: 39, .. }
+    ));
+
+    unsafe {
+        pg.map(
+            MapConfig::new(
+                0xffffffc000000000usize.into(),
+                0x0usize.into(),
+                0x1000,
+                PteImpl(0),
+                false,
+                false,
+            ),
+            &mut access,
+        )
+        .unwrap();
+    }
+}
+
+#[test]
+fn test_block_conflict() {
+    let (mut access, mut pg) = new_alloc_and_table();
+    unsafe {
+        pg.map(
+            MapConfig::new(
+                0x40000000usize.into(),
+                0x40000000usize.into(),
+                GB,
+                PteImpl(0),
+                true,
+                false,
+            ),
+            &mut access,
+        )
+        .unwrap();
+    }
+
+    let err = unsafe {
+        pg.map(
+            MapConfig::new(
+                0x40200000usize.into(),
+                0x80000000usize.into(),
+                0x1000,
+                PteImpl(0),
+                false,
+                false,
+            ),
+            &mut access,
+        )
+    }
+    .unwrap_err();
+
+    assert_eq!(
+        err,
+        PagingError::BlockConflict {
+            op: PagingOp::Map,
+            vaddr: 0x40200000usize.into(),
+            paddr: 0x40000000usize.into(),
+            level: 3,
+        }
+    );
+}
+
+#[test]
+fn test_already_mapped() {
+    let (mut access, mut pg) = new_alloc_and_table();
+    unsafe {
+        pg.map(
+            MapConfig::new(
+                0x40000000usize.into(),
+                0x40000000usize.into(),
+                0x2000,
+                PteImpl(0),
+                false,
+                false,
+            ),
+            &mut access,
+        )
+        .unwrap();
+    }
+
+    let err = unsafe {
+        pg.map(
+            MapConfig::new(
+                0x40001000usize.into(),
+                0x80000000usize.into(),
+                0x1000,
+                PteImpl(0),
+                false,
+                false,
+            ),
+            &mut access,
+        )
+    }
+    .unwrap_err();
+
+    assert_eq!(
+        err,
+        PagingError::AlreadyMapped {
+            op: PagingOp::Map,
+            vaddr: 0x40001000usize.into(),
+            paddr: 0x40001000usize.into(),
+            level: 1,
+        }
+    );
+}
+
+#[test]
+fn test_max_level() {
+    let (mut access, mut pg) = new_alloc_and_table();
+    unsafe {
+        pg.map(
+            MapConfig::new(
+                0xffff000040000000usize.into(),
+                0x40000000usize.into(),
+                2 * GB,
+                PteImpl(0),
+                true,
+                false,
+            )
+            .with_levels(1, 2),
+            &mut access,
+        )
+        .unwrap();
+    }
+
+    let list = pg.iter_all(&access).collect::<Vec<_>>();
+    let blocks = list.iter().filter(|i| i.pte.is_huge()).collect::<Vec<_>>();
+    assert_eq!(blocks.len(), 1024);
+    assert!(blocks.iter().all(|i| i.level == 2));
+}
+
+#[test]
+fn test_page_size() {
+    let (mut access, mut pg) = new_alloc_and_table();
+    unsafe {
+        pg.map(
+            MapConfig::new(
+                0xffff000040000000usize.into(),
+                0x40000000usize.into(),
+                GB,
+                PteImpl(0),
+                true,
+                false,
+            )
+            .with_page_size::<Table>(2 * MB),
+            &mut access,
+        )
+        .unwrap();
+    }
+    assert!(
+        pg.iter_all(&access)
+            .filter(|i| i.pte.is_huge())
+            .all(|i| i.level == 2)
+    );
+
+    let err = unsafe {
+        pg.map(
+            MapConfig::new(
+                0xffff000080000000usize.into(),
+                0x80000000usize.into(),
+                GB,
+                PteImpl(0),
+                true,
+                false,
+            )
+            .with_page_size::<Table>(3 * MB),
+            &mut access,
+        )
+    }
+    .unwrap_err();
+    assert!(matches!(err, PagingError::InvalidLevel { .. }));
+}
+
+#[test]
+fn test_min_level() {
+    let (mut access, mut pg) = new_alloc_and_table();
+    let err = unsafe {
+        pg.map(
+            MapConfig::new(
+                0xffff000040200000usize.into(),
+                0x40200000usize.into(),
+                GB,
+                PteImpl(0),
+                true,
+                false,
+            )
+            .with_levels(3, 3),
+            &mut access,
+        )
+    }
+    .unwrap_err();
+
+    assert_eq!(
+        err,
+        PagingError::NotAligned {
+            op: PagingOp::Map,
+            field: "vaddr",
+            vaddr: 0xffff000040200000usize.into(),
+            paddr: 0x40200000usize.into(),
+            align: GB,
+        }
+    );
+    assert_eq!(pg.iter_all(&access).count(), 0);
+}
+
 // #[test]
 // fn test_2() {
 //     let _ = env_logger::builder()
@@ -381,3 +681,101 @@ fn test_release() {
 //     // }
 //     pg.release(&mut access);
 // }
+
+#[test]
+fn test_unmap() {
+    let (mut access, mut pg) = new_alloc_and_table();
+    unsafe {
+        pg.map(
+            MapConfig::new(
+                0xffff000040000000usize.into(),
+                0x40000000usize.into(),
+                4 * MB,
+                PteImpl(0),
+                true,
+                false,
+            ),
+            &mut access,
+        )
+        .unwrap();
+        pg.map(
+            MapConfig::new(
+                0xffff000040600000usize.into(),
+                0x80000000usize.into(),
+                0x3000,
+                PteImpl(0),
+                false,
+                false,
+            ),
+            &mut access,
+        )
+        .unwrap();
+    }
+    assert_eq!(pg.iter_all(&access).filter(|i| i.pte.is_huge()).count(), 2);
+
+    let err = unsafe { pg.unmap(0xffff000040000000usize.into(), 0x1000, false, &mut access) }
+        .unwrap_err();
+    assert_eq!(
+        err,
+        PagingError::BlockConflict {
+            op: PagingOp::Unmap,
+            vaddr: 0xffff000040000000usize.into(),
+            paddr: 0x40000000usize.into(),
+            level: 2,
+        }
+    );
+
+    // The hole between the block and the pages is skipped.
+    unsafe {
+        pg.unmap(
+            0xffff000040200000usize.into(),
+            4 * MB + 0x1000,
+            false,
+            &mut access,
+        )
+        .unwrap();
+    }
+    let leaves = pg
+        .iter_all(&access)
+        .filter(|i| i.pte.valid() && (i.level == 1 || i.pte.is_huge()))
+        .map(|i| i.vaddr.raw() & ((1 << 48) - 1))
+        .collect::<Vec<_>>();
+    assert_eq!(leaves, [0x000040000000, 0x000040601000, 0x000040602000]);
+}
+
+#[test]
+fn test_translate() {
+    let (mut access, mut pg) = new_alloc_and_table();
+    unsafe {
+        pg.map(
+            MapConfig::new(
+                0xffff000040000000usize.into(),
+                0x40000000usize.into(),
+                2 * MB,
+                PteImpl(0),
+                true,
+                false,
+            ),
+            &mut access,
+        )
+        .unwrap();
+        pg.map(
+            MapConfig::new(
+                0xffff000040600000usize.into(),
+                0x80000000usize.into(),
+                0x2000,
+                PteImpl(0),
+                false,
+                false,
+            ),
+            &mut access,
+        )
+        .unwrap();
+    }
+    let translate = |va: usize| pg.translate(va.into(), &access).map(|pa| pa.raw());
+
+    assert_eq!(translate(0xffff000040123456), Some(0x40123456));
+    assert_eq!(translate(0xffff000040601abc), Some(0x80001abc));
+    assert_eq!(translate(0xffff000040602000), None);
+    assert_eq!(translate(0xffff000040200000), None);
+}
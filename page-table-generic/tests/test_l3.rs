@@ -1,3 +1,5 @@
+#![allow(clippy::uninlined_format_args)]
+
 use std::{
     alloc::{self, Layout},
     fmt::Debug,
@@ -145,7 +147,7 @@ fn test_new() {
     unsafe {
         pg.map(
             MapConfig::new(
-                0xffffff3080200000usize.into(),
+                0xffffffc080200000usize.into(),
                 0x80200000usize.into(),
                 2 * MB,
                 PteImpl(0xef),
@@ -180,6 +182,30 @@ fn test_new() {
     assert_eq!(list.len(), 2);
 }
 
+#[test]
+fn test_out_of_range() {
+    let (mut access, mut pg) = new_alloc_and_table();
+    let err = unsafe {
+        pg.map(
+            MapConfig::new(
+                0xffffff3080200000usize.into(),
+                0x80200000usize.into(),
+                2 * MB,
+                PteImpl(0xef),
+                true,
+                false,
+            ),
+            &mut access,
+        )
+    }
+    .unwrap_err();
+
+    assert!(matches!(
+        err,
+        PagingError::OutOfRange { valid_bits: 39, .. }
+    ));
+}
+
 #[test]
 fn test_new2() {
     let _ = env_logger::builder()
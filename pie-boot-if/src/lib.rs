@@ -3,13 +3,23 @@
 use core::{fmt::Debug, mem::MaybeUninit, ptr::NonNull};
 
 mod memregions;
+mod regionmap;
 
 pub use heapless::{String, Vec};
+pub use kdef_pgtable::VaLayout;
 pub use memregions::*;
+pub use regionmap::*;
+
+/// 内核与 loader 之间的接口标识，写在 [`BootInfo::magic`] 和 loader 的 `EarlyBootArgs::magic` 中。
+///
+/// 高 32 位为 `"PIEB"`，低 32 位为接口版本，任何一边的结构体布局改变时都要增加版本。
+pub const BOOT_MAGIC: usize = 0x5049_4542_0000_0009;
 
 #[repr(align(64))]
 #[derive(Debug, Clone)]
 pub struct BootInfo {
+    /// [`BOOT_MAGIC`]，不一致说明 loader 与内核版本不匹配
+    pub magic: usize,
     /// CPU 硬件ID
     pub cpu_id: usize,
     /// 内核镜像物理地址
@@ -26,6 +36,14 @@ pub struct BootInfo {
     pub debug_console: Option<DebugConsole>,
     /// 可用内存起始地址
     pub free_memory_start: *mut u8,
+    /// 运行时虚拟地址空间布局
+    pub va_layout: VaLayout,
+    /// KASLR 随机偏移，内核实际虚拟地址相对链接地址，未启用时为 0
+    pub kaslr_offset: usize,
+    /// 引导程序加载的 initrd 和其它模块
+    pub modules: Vec<BootModule, MAX_BOOT_MODULES>,
+    /// 内核命令行，来自 `/chosen/bootargs`
+    pub cmdline: String<CMDLINE_CAPACITY>,
 }
 
 unsafe impl Send for BootInfo {}
@@ -39,6 +57,42 @@ impl BootInfo {
     pub fn kcode_offset(&self) -> usize {
         self.kimage_start_vma as usize - self.kimage_start_lma as usize
     }
+
+    /// 模块内容，通过线性映射访问
+    pub fn module_data(&self, module: &BootModule) -> &'static [u8] {
+        let ptr = (module.start + self.va_layout.kliner_offset) as *const u8;
+        unsafe { core::slice::from_raw_parts(ptr, module.end - module.start) }
+    }
+
+    /// initrd 内容，见 [`BootModule::INITRD`]
+    pub fn initrd(&self) -> Option<&'static [u8]> {
+        self.modules
+            .iter()
+            .find(|m| m.name == BootModule::INITRD)
+            .map(|m| self.module_data(m))
+    }
+}
+
+/// 内核命令行最大长度
+pub const CMDLINE_CAPACITY: usize = 2048;
+
+/// 最多记录的模块数
+pub const MAX_BOOT_MODULES: usize = 8;
+const MODULE_NAME_CAPACITY: usize = 32;
+
+/// 引导程序加载到内存中的模块，如 initrd
+#[derive(Debug, Clone)]
+pub struct BootModule {
+    /// `/chosen/linux,initrd-*` 为 [`BootModule::INITRD`]，`/chosen/module@*` 为节点名
+    pub name: String<MODULE_NAME_CAPACITY>,
+    /// 物理起始地址
+    pub start: usize,
+    /// 物理结束地址（不含）
+    pub end: usize,
+}
+
+impl BootModule {
+    pub const INITRD: &str = "initrd";
 }
 
 impl Default for BootInfo {
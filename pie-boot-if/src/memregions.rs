@@ -1,4 +1,4 @@
-use core::{fmt, ops, slice};
+use core::{fmt, ops, ops::Range, slice};
 
 #[derive(Clone, Copy)]
 pub struct MemoryRegion {
@@ -12,6 +12,18 @@ pub struct MemoryRegion {
     pub kind: MemoryRegionKind,
 }
 
+impl MemoryRegion {
+    /// Size of the region in bytes.
+    pub fn size(&self) -> usize {
+        self.end.saturating_sub(self.start)
+    }
+
+    /// Returns whether `paddr` lies inside the region.
+    pub fn contains(&self, paddr: usize) -> bool {
+        (self.start..self.end).contains(&paddr)
+    }
+}
+
 impl fmt::Debug for MemoryRegion {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("MemoryRegion")
@@ -43,6 +55,12 @@ pub enum MemoryRegionKind {
     ///
     /// Contains the E820 memory type.
     UnknownBios(u32),
+    /// Memory that is free to use: [`Ram`][MemoryRegionKind::Ram] minus every
+    /// other region, see [`carve_usable`].
+    Usable,
+    /// A module loaded by the bootloader, e.g. the initrd, see
+    /// [`BootInfo::modules`](crate::BootInfo::modules).
+    Module,
 }
 
 /// FFI-safe slice of [`MemoryRegion`] structs, semantically equivalent to
@@ -72,6 +90,72 @@ impl ops::DerefMut for MemoryRegions {
     }
 }
 
+impl MemoryRegions {
+    /// Iterates over the [`Usable`][MemoryRegionKind::Usable] regions.
+    pub fn usable(&self) -> impl Iterator<Item = &MemoryRegion> {
+        self.iter()
+            .filter(|r| matches!(r.kind, MemoryRegionKind::Usable))
+    }
+
+    /// Total size of the installed RAM in bytes.
+    ///
+    /// Counts every region backed by RAM: [`Ram`][MemoryRegionKind::Ram],
+    /// [`Usable`][MemoryRegionKind::Usable], [`Bootloader`][MemoryRegionKind::Bootloader],
+    /// [`Module`][MemoryRegionKind::Module] and [`Reserved`][MemoryRegionKind::Reserved].
+    /// Overlapping regions are counted once, so the result is the same for
+    /// raw lists, where `Ram` overlaps the other kinds, and for lists
+    /// normalized by a [`RegionMap`](crate::RegionMap).
+    pub fn total_ram(&self) -> usize {
+        let ram = || {
+            self.iter().filter(|r| {
+                matches!(
+                    r.kind,
+                    MemoryRegionKind::Ram
+                        | MemoryRegionKind::Usable
+                        | MemoryRegionKind::Bootloader
+                        | MemoryRegionKind::Module
+                        | MemoryRegionKind::Reserved
+                )
+            })
+        };
+        // Walk the union of the regions without allocating.
+        let mut total = 0;
+        let mut pos = 0;
+        loop {
+            let covered = ram().filter(|r| r.contains(pos)).map(|r| r.end).max();
+            if let Some(end) = covered {
+                total += end - pos;
+                pos = end;
+                continue;
+            }
+            match ram()
+                .filter(|r| r.start > pos && r.size() > 0)
+                .map(|r| r.start)
+                .min()
+            {
+                Some(start) => pos = start,
+                None => return total,
+            }
+        }
+    }
+
+    /// Finds the region containing `paddr`.
+    ///
+    /// Regions of a normalized list do not overlap. In a raw list
+    /// [`Ram`][MemoryRegionKind::Ram] may overlap other kinds, and the more
+    /// specific region is returned.
+    pub fn find(&self, paddr: usize) -> Option<&MemoryRegion> {
+        let mut found = None;
+        for r in self.iter().filter(|r| r.contains(paddr)) {
+            if !matches!(r.kind, MemoryRegionKind::Ram) {
+                return Some(r);
+            }
+            found = Some(r);
+        }
+        found
+    }
+}
+
 impl From<&'static mut [MemoryRegion]> for MemoryRegions {
     fn from(regions: &'static mut [MemoryRegion]) -> Self {
         MemoryRegions {
@@ -101,3 +185,184 @@ impl fmt::Debug for MemoryRegions {
 
 unsafe impl Send for MemoryRegions {}
 unsafe impl Sync for MemoryRegions {}
+
+/// Carves the [`Usable`][MemoryRegionKind::Usable] regions out of `regions`.
+///
+/// Every [`Ram`][MemoryRegionKind::Ram] region is split around all regions of
+/// other kinds and around `holes`, e.g. the kernel image or the FDT, which are
+/// not recorded in `regions`. Existing `Usable` entries are ignored. Does not
+/// allocate, `regions` need not be sorted.
+pub fn carve_usable<'a>(regions: &'a [MemoryRegion], holes: &'a [Range<usize>]) -> CarveUsable<'a> {
+    CarveUsable {
+        regions,
+        holes,
+        idx: 0,
+        cursor: None,
+    }
+}
+
+/// Iterator returned by [`carve_usable`].
+pub struct CarveUsable<'a> {
+    regions: &'a [MemoryRegion],
+    holes: &'a [Range<usize>],
+    idx: usize,
+    /// Position inside `regions[idx]`, `None` before the region is entered.
+    cursor: Option<usize>,
+}
+
+impl CarveUsable<'_> {
+    /// The hole overlapping `[start, end)` that starts first.
+    fn first_hole(&self, start: usize, end: usize) -> Option<Range<usize>> {
+        let regions = self
+            .regions
+            .iter()
+            .filter(|r| !matches!(r.kind, MemoryRegionKind::Ram | MemoryRegionKind::Usable))
+            .map(|r| r.start..r.end);
+        regions
+            .chain(self.holes.iter().cloned())
+            .filter(|h| !h.is_empty() && h.start < end && h.end > start)
+            .min_by_key(|h| h.start)
+    }
+}
+
+impl Iterator for CarveUsable<'_> {
+    type Item = MemoryRegion;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(ram) = self.regions.get(self.idx) {
+            if !matches!(ram.kind, MemoryRegionKind::Ram) {
+                self.idx += 1;
+                continue;
+            }
+            let start = *self.cursor.get_or_insert(ram.start);
+            if start >= ram.end {
+                self.idx += 1;
+                self.cursor = None;
+                continue;
+            }
+
+            let end = match self.first_hole(start, ram.end) {
+                Some(hole) => {
+                    self.cursor = Some(hole.end);
+                    hole.start
+                }
+                None => {
+                    self.cursor = Some(ram.end);
+                    ram.end
+                }
+            };
+            if start < end {
+                return Some(MemoryRegion {
+                    start,
+                    end,
+                    kind: MemoryRegionKind::Usable,
+                });
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::{boxed::Box, vec, vec::Vec};
+
+    use super::*;
+
+    fn region(start: usize, end: usize, kind: MemoryRegionKind) -> MemoryRegion {
+        MemoryRegion { start, end, kind }
+    }
+
+    fn carve(regions: &[MemoryRegion], holes: &[Range<usize>]) -> Vec<Range<usize>> {
+        carve_usable(regions, holes)
+            .map(|r| r.start..r.end)
+            .collect()
+    }
+
+    #[test]
+    fn test_carve_split() {
+        let regions = [
+            region(0x5000, 0x6000, MemoryRegionKind::Bootloader),
+            region(0x0, 0x10000, MemoryRegionKind::Ram),
+            region(0x2000, 0x3000, MemoryRegionKind::Reserved),
+        ];
+        assert_eq!(
+            carve(&regions, &[0x8000..0x9000, 0xf000..0xf000]),
+            vec![0x0..0x2000, 0x3000..0x5000, 0x6000..0x8000, 0x9000..0x10000]
+        );
+    }
+
+    #[test]
+    fn test_carve_overlapping_holes() {
+        let regions = [
+            region(0x1000, 0x8000, MemoryRegionKind::Ram),
+            region(0x0, 0x2000, MemoryRegionKind::Reserved),
+            region(0x3000, 0x5000, MemoryRegionKind::Reserved),
+            region(0x7000, 0x9000, MemoryRegionKind::Reserved),
+        ];
+        assert_eq!(
+            carve(&regions, &[0x4000..0x6000, 0x3800..0x4000]),
+            vec![0x2000..0x3000, 0x6000..0x7000]
+        );
+    }
+
+    #[test]
+    fn test_carve_fully_covered() {
+        let regions = [
+            region(0x1000, 0x2000, MemoryRegionKind::Ram),
+            region(0x3000, 0x4000, MemoryRegionKind::Ram),
+            region(0x0, 0x2000, MemoryRegionKind::Reserved),
+            region(0x3000, 0x3800, MemoryRegionKind::Usable),
+        ];
+        assert_eq!(carve(&regions, &[]), vec![0x3000..0x4000]);
+    }
+
+    #[test]
+    fn test_regions_helpers() {
+        let list: &'static mut [MemoryRegion] = Box::leak(Box::new([
+            region(0x0, 0x10000, MemoryRegionKind::Ram),
+            region(0x20000, 0x30000, MemoryRegionKind::Ram),
+            region(0x2000, 0x3000, MemoryRegionKind::Reserved),
+            region(0x0, 0x2000, MemoryRegionKind::Usable),
+            region(0x3000, 0x10000, MemoryRegionKind::Usable),
+        ]));
+        let regions = MemoryRegions::from(list);
+
+        assert_eq!(regions.total_ram(), 0x20000);
+        assert_eq!(
+            regions.usable().map(MemoryRegion::size).sum::<usize>(),
+            0xf000
+        );
+        assert_eq!(
+            regions.find(0x2800).map(|r| r.kind),
+            Some(MemoryRegionKind::Reserved)
+        );
+        assert_eq!(
+            regions.find(0x20000).map(|r| r.kind),
+            Some(MemoryRegionKind::Ram)
+        );
+        assert!(regions.find(0x18000).is_none());
+    }
+
+    #[test]
+    fn test_total_ram_normalized() {
+        let list: &'static mut [MemoryRegion] = Box::leak(Box::new([
+            region(0x0, 0x1000, MemoryRegionKind::Bootloader),
+            region(0x1000, 0x2000, MemoryRegionKind::Usable),
+            region(0x2000, 0x3000, MemoryRegionKind::Reserved),
+            region(0x3000, 0x4000, MemoryRegionKind::Module),
+            region(0x4000, 0x5000, MemoryRegionKind::Ram),
+            region(0x8000, 0x9000, MemoryRegionKind::Usable),
+            region(0x10000, 0x11000, MemoryRegionKind::UnknownUefi(7)),
+        ]));
+        let regions = MemoryRegions::from(list);
+
+        assert_eq!(regions.total_ram(), 0x6000);
+        assert_eq!(
+            regions.find(0x4800).map(|r| r.kind),
+            Some(MemoryRegionKind::Ram)
+        );
+    }
+}
@@ -0,0 +1,305 @@
+use core::ops::{Deref, DerefMut};
+
+use crate::{MemoryRegion, MemoryRegionKind};
+
+impl MemoryRegionKind {
+    /// Precedence used by [`RegionMap`] when regions overlap, the higher one wins:
+    /// `Reserved` > unknown firmware types > `Module` > `Bootloader` > `Usable` > `Ram`.
+    pub const fn precedence(&self) -> u8 {
+        match self {
+            MemoryRegionKind::Ram => 0,
+            MemoryRegionKind::Usable => 1,
+            MemoryRegionKind::Bootloader => 2,
+            MemoryRegionKind::Module => 3,
+            MemoryRegionKind::UnknownUefi(_) | MemoryRegionKind::UnknownBios(_) => 4,
+            MemoryRegionKind::Reserved => 5,
+        }
+    }
+}
+
+/// Backing storage of a [`RegionMap`].
+pub trait RegionVec: DerefMut<Target = [MemoryRegion]> {
+    type Error;
+
+    fn insert(&mut self, index: usize, region: MemoryRegion) -> Result<(), Self::Error>;
+
+    fn remove(&mut self, index: usize) -> MemoryRegion;
+}
+
+impl<const N: usize> RegionVec for heapless::Vec<MemoryRegion, N> {
+    type Error = MemoryRegion;
+
+    fn insert(&mut self, index: usize, region: MemoryRegion) -> Result<(), Self::Error> {
+        heapless::Vec::insert(self, index, region)
+    }
+
+    fn remove(&mut self, index: usize) -> MemoryRegion {
+        heapless::Vec::remove(self, index)
+    }
+}
+
+/// Interval map of memory regions.
+///
+/// Spans are sorted by address, never overlap and adjacent spans of the same kind
+/// are merged. Inserting a region splits the spans it overlaps, the overlapped
+/// parts take the kind with the higher [`precedence`](MemoryRegionKind::precedence).
+pub struct RegionMap<V> {
+    spans: V,
+}
+
+impl<V: RegionVec> RegionMap<V> {
+    /// `spans` must be empty or already sorted and non-overlapping.
+    pub const fn new(spans: V) -> Self {
+        Self { spans }
+    }
+
+    /// The backing storage, callers must keep the spans sorted and non-overlapping.
+    pub fn storage_mut(&mut self) -> &mut V {
+        &mut self.spans
+    }
+
+    /// Inserts `region`.
+    ///
+    /// On error the map stays valid but `region` may be only partly inserted.
+    pub fn insert(&mut self, region: MemoryRegion) -> Result<(), V::Error> {
+        let MemoryRegion { start, end, kind } = region;
+        if start >= end {
+            return Ok(());
+        }
+
+        let first = self.spans.partition_point(|r| r.end <= start);
+        let mut i = first;
+        let mut pos = start;
+        while pos < end {
+            match self.spans.get(i).copied() {
+                Some(span) if span.start <= pos => {
+                    // insert the right part before truncating `span`, so a failed
+                    // insert leaves `span` whole
+                    if span.start < pos {
+                        // split off the part left of `pos`
+                        self.spans
+                            .insert(i + 1, MemoryRegion { start: pos, ..span })?;
+                        self.spans[i].end = pos;
+                        i += 1;
+                    } else if span.end > end {
+                        // split off the part right of `end`
+                        self.spans
+                            .insert(i + 1, MemoryRegion { start: end, ..span })?;
+                        self.spans[i].end = end;
+                    } else {
+                        if kind.precedence() > span.kind.precedence() {
+                            self.spans[i].kind = kind;
+                        }
+                        pos = span.end;
+                        i += 1;
+                    }
+                }
+                next => {
+                    let gap_end = next.map_or(end, |r| r.start.min(end));
+                    self.spans.insert(
+                        i,
+                        MemoryRegion {
+                            start: pos,
+                            end: gap_end,
+                            kind,
+                        },
+                    )?;
+                    pos = gap_end;
+                    i += 1;
+                }
+            }
+        }
+
+        self.coalesce(first.saturating_sub(1), i + 1);
+        Ok(())
+    }
+
+    /// Merges adjacent spans of the same kind in `[from, to)`.
+    fn coalesce(&mut self, from: usize, to: usize) {
+        let mut to = to.min(self.spans.len());
+        let mut i = from;
+        while i + 1 < to {
+            let (a, b) = (self.spans[i], self.spans[i + 1]);
+            if a.end == b.start && a.kind == b.kind {
+                self.spans[i].end = b.end;
+                self.spans.remove(i + 1);
+                to -= 1;
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// The span containing `paddr`.
+    pub fn find(&self, paddr: usize) -> Option<&MemoryRegion> {
+        let i = self.spans.partition_point(|r| r.end <= paddr);
+        self.spans.get(i).filter(|r| r.contains(paddr))
+    }
+
+    /// Spans of `kind`, sorted by address.
+    pub fn iter_kind(&self, kind: MemoryRegionKind) -> impl Iterator<Item = &MemoryRegion> {
+        self.spans.iter().filter(move |r| r.kind == kind)
+    }
+}
+
+impl<V: RegionVec> Deref for RegionMap<V> {
+    type Target = [MemoryRegion];
+
+    fn deref(&self) -> &Self::Target {
+        &self.spans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+    use MemoryRegionKind::*;
+
+    type Map = RegionMap<heapless::Vec<MemoryRegion, 16>>;
+
+    fn map(regions: &[(usize, usize, MemoryRegionKind)]) -> Map {
+        let mut m = Map::new(heapless::Vec::new());
+        for &(start, end, kind) in regions {
+            m.insert(MemoryRegion { start, end, kind }).unwrap();
+        }
+        m
+    }
+
+    fn spans(m: &Map) -> Vec<(usize, usize, MemoryRegionKind)> {
+        m.iter().map(|r| (r.start, r.end, r.kind)).collect()
+    }
+
+    #[test]
+    fn test_split_and_override() {
+        let m = map(&[
+            (0x0, 0x10000, Ram),
+            (0x2000, 0x3000, Reserved),
+            (0x8000, 0x12000, Bootloader),
+        ]);
+        assert_eq!(
+            spans(&m),
+            [
+                (0x0, 0x2000, Ram),
+                (0x2000, 0x3000, Reserved),
+                (0x3000, 0x8000, Ram),
+                (0x8000, 0x12000, Bootloader),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_precedence_is_order_independent() {
+        let a = map(&[
+            (0x1000, 0x4000, Reserved),
+            (0x0, 0x8000, Ram),
+            (0x3000, 0x6000, Bootloader),
+        ]);
+        let b = map(&[
+            (0x3000, 0x6000, Bootloader),
+            (0x0, 0x8000, Ram),
+            (0x1000, 0x4000, Reserved),
+        ]);
+        assert_eq!(spans(&a), spans(&b));
+        assert_eq!(
+            spans(&a),
+            [
+                (0x0, 0x1000, Ram),
+                (0x1000, 0x4000, Reserved),
+                (0x4000, 0x6000, Bootloader),
+                (0x6000, 0x8000, Ram),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_module_over_bootloader() {
+        let m = map(&[
+            (0x0, 0x10000, Ram),
+            (0x4000, 0x9000, Bootloader),
+            (0x6000, 0x7000, Module),
+            (0x6800, 0x7800, Reserved),
+        ]);
+        assert_eq!(
+            spans(&m),
+            [
+                (0x0, 0x4000, Ram),
+                (0x4000, 0x6000, Bootloader),
+                (0x6000, 0x6800, Module),
+                (0x6800, 0x7800, Reserved),
+                (0x7800, 0x9000, Bootloader),
+                (0x9000, 0x10000, Ram),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_adjacent() {
+        let m = map(&[
+            (0x0, 0x1000, Ram),
+            (0x2000, 0x3000, Ram),
+            (0x1000, 0x2000, Ram),
+            (0x4000, 0x5000, Reserved),
+            (0x3800, 0x4800, Reserved),
+        ]);
+        assert_eq!(spans(&m), [(0x0, 0x3000, Ram), (0x3800, 0x5000, Reserved)]);
+    }
+
+    #[test]
+    fn test_fill_gaps() {
+        let m = map(&[
+            (0x1000, 0x2000, Reserved),
+            (0x3000, 0x4000, Reserved),
+            (0x0, 0x5000, Ram),
+        ]);
+        assert_eq!(
+            spans(&m),
+            [
+                (0x0, 0x1000, Ram),
+                (0x1000, 0x2000, Reserved),
+                (0x2000, 0x3000, Ram),
+                (0x3000, 0x4000, Reserved),
+                (0x4000, 0x5000, Ram),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find() {
+        let m = map(&[(0x0, 0x4000, Ram), (0x1000, 0x2000, Reserved)]);
+        assert_eq!(m.find(0x1800).map(|r| r.kind), Some(Reserved));
+        assert_eq!(m.find(0x2000).map(|r| r.kind), Some(Ram));
+        assert!(m.find(0x4000).is_none());
+        assert_eq!(m.iter_kind(Ram).count(), 2);
+    }
+
+    #[test]
+    fn test_full_keeps_spans() {
+        let mut m = RegionMap::new(heapless::Vec::<MemoryRegion, 2>::new());
+        m.insert(MemoryRegion {
+            start: 0x0,
+            end: 0x1000,
+            kind: Ram,
+        })
+        .unwrap();
+        m.insert(MemoryRegion {
+            start: 0x1000,
+            end: 0x4000,
+            kind: Reserved,
+        })
+        .unwrap();
+
+        // splitting the Reserved span needs a third slot
+        let r = m.insert(MemoryRegion {
+            start: 0x2000,
+            end: 0x3000,
+            kind: Usable,
+        });
+        assert!(r.is_err());
+        let spans: Vec<_> = m.iter().map(|r| (r.start, r.end, r.kind)).collect();
+        assert_eq!(spans, [(0x0, 0x1000, Ram), (0x1000, 0x4000, Reserved)]);
+    }
+}
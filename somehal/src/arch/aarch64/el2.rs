@@ -69,3 +69,12 @@ pub fn set_kernal_table(tb: PageTable) {
     barrier::dsb(barrier::SY);
     barrier::isb(barrier::SY);
 }
+
+/// 当前 CPU 的每 CPU 区域
+pub(crate) fn percpu_base() -> usize {
+    TPIDR_EL2.get() as _
+}
+
+pub(crate) fn set_percpu_base(base: usize) {
+    TPIDR_EL2.set(base as _);
+}
@@ -0,0 +1,190 @@
+//! 固定映射区，虚拟地址在编译期确定，见 [`kdef_pgtable::VmLayout::fixmap`]。
+//!
+//! 在堆和 [`mmu::mmap`](super::mmu::mmap) 可用之前也能使用：此时映射写入 loader
+//! 建立的启动页表，所需的中间页表取自静态页池。`init_mmu` 切换页表时会把已建立的
+//! 映射搬到内核页表。
+
+use core::ptr::NonNull;
+
+use kdef_pgtable::{FIXMAP_VSIZE, PAGE_SIZE, SZ_2M, layout};
+use num_align::NumAlign;
+use page_table_generic::{Access, PageTableRef, PagingError, PhysAddr};
+use spin::Mutex;
+
+use super::mmu::{Allocator, KERNAL_TABLE, Table, map_config};
+use crate::{
+    boot_info,
+    common::mem::{AccessKind, CacheKind, MapRangeConfig, kliner_offset, va_layout},
+};
+
+/// 预留给 FDT 的大小，FDT 不一定页对齐，所以多留一页
+pub const FIXMAP_FDT_SIZE: usize = SZ_2M;
+const FDT_PAGES: usize = FIXMAP_FDT_SIZE / PAGE_SIZE + 1;
+
+/// fixmap 槽位，每个槽位占一页，[`FixMap::Fdt`] 占 `FIXMAP_FDT_SIZE` 大小。
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixMap {
+    /// 早期串口
+    EarlyCon,
+    /// FDT
+    Fdt,
+    /// 临时清零窗口
+    PageZero = 1 + FDT_PAGES,
+    /// 通用临时映射
+    Temp,
+}
+
+impl FixMap {
+    pub const COUNT: usize = Self::Temp as usize + 1;
+
+    /// 槽位的虚拟地址
+    pub const fn vaddr(self) -> usize {
+        layout().fixmap.start + self as usize * PAGE_SIZE
+    }
+
+    /// 槽位占用的大小
+    pub const fn size(self) -> usize {
+        match self {
+            Self::Fdt => FDT_PAGES * PAGE_SIZE,
+            _ => PAGE_SIZE,
+        }
+    }
+}
+
+const _: () = assert!(FixMap::COUNT * PAGE_SIZE <= FIXMAP_VSIZE);
+
+/// 每页当前映射的物理页，`init_mmu` 用来重建映射
+static SLOTS: Mutex<[Option<(usize, CacheKind)>; FixMap::COUNT]> =
+    Mutex::new([None; FixMap::COUNT]);
+
+/// 启动页表上建立 fixmap 所需的最多页表数：除根以外每级一页，叶子页表覆盖整个 fixmap
+const POOL_PAGES: usize = 3 + FIXMAP_VSIZE.div_ceil(PAGE_SIZE / 8 * PAGE_SIZE);
+
+static mut POOL: [u8; (POOL_PAGES + 1) * PAGE_SIZE] = [0; (POOL_PAGES + 1) * PAGE_SIZE];
+static mut POOL_USED: usize = 0;
+
+/// 从静态页池分配页表，只在内核页表建立前使用
+struct PoolAccess;
+
+impl Access for PoolAccess {
+    unsafe fn alloc(&mut self, layout: core::alloc::Layout) -> Option<PhysAddr> {
+        if layout.size() > PAGE_SIZE || layout.align() > PAGE_SIZE {
+            return None;
+        }
+        unsafe {
+            if POOL_USED >= POOL_PAGES {
+                return None;
+            }
+            let start = (&raw mut POOL as usize).align_up(PAGE_SIZE);
+            let vaddr = start + POOL_USED * PAGE_SIZE;
+            POOL_USED += 1;
+            Some((vaddr - boot_info().kcode_offset()).into())
+        }
+    }
+
+    unsafe fn dealloc(&mut self, _ptr: PhysAddr, _layout: core::alloc::Layout) {}
+
+    fn phys_to_mut(&self, phys: PhysAddr) -> *mut u8 {
+        (phys.raw() + kliner_offset()) as *mut u8
+    }
+}
+
+fn boot_table() -> Table<'static> {
+    PageTableRef::from_addr(
+        (boot_info().pg_start as usize).into(),
+        va_layout().page_levels,
+    )
+}
+
+fn slot_config(vaddr: usize, paddr: usize, cache: CacheKind) -> MapRangeConfig {
+    MapRangeConfig {
+        vaddr: vaddr as *mut u8,
+        paddr,
+        size: PAGE_SIZE,
+        name: "fixmap",
+        cache,
+        access: AccessKind::ReadWrite,
+        cpu_share: true,
+        max_page_size: Some(PAGE_SIZE),
+        min_page_size: None,
+    }
+}
+
+/// 把 `idx` 映射到 `paddr` 所在的页，返回 `paddr` 对应的虚拟地址。
+///
+/// 槽位已被映射时返回 [`PagingError::AlreadyMapped`]，需要先 [`clear_fixmap`]。
+pub fn set_fixmap(idx: FixMap, paddr: usize, cache: CacheKind) -> Result<NonNull<u8>, PagingError> {
+    set_fixmap_range(idx, paddr, 1, cache)
+}
+
+/// 把 `[paddr, paddr + size)` 映射到 `idx` 开始的虚拟地址，用于 [`FixMap::Fdt`] 等多页槽位。
+///
+/// # Panics
+/// 超出槽位大小时 panic。
+pub fn set_fixmap_range(
+    idx: FixMap,
+    paddr: usize,
+    size: usize,
+    cache: CacheKind,
+) -> Result<NonNull<u8>, PagingError> {
+    let offset = paddr % PAGE_SIZE;
+    let start = paddr - offset;
+    let size = (offset + size.max(1)).align_up(PAGE_SIZE);
+    assert!(
+        size <= idx.size(),
+        "fixmap {idx:?}: {size:#x} exceeds the slot size {:#x}",
+        idx.size()
+    );
+
+    let first = idx as usize;
+    let mut slots = SLOTS.lock();
+    let mut kernel = KERNAL_TABLE.lock();
+    for i in 0..size / PAGE_SIZE {
+        let paddr = start + i * PAGE_SIZE;
+        let config = map_config(slot_config(idx.vaddr() + i * PAGE_SIZE, paddr, cache), true);
+        unsafe {
+            match kernel.as_mut() {
+                Some(table) => table.map(config, &mut Allocator)?,
+                None => boot_table().map(config, &mut PoolAccess)?,
+            }
+        }
+        slots[first + i] = Some((paddr, cache));
+    }
+
+    Ok(NonNull::new((idx.vaddr() + offset) as *mut u8).unwrap())
+}
+
+/// 解除 `idx` 槽位的映射
+pub fn clear_fixmap(idx: FixMap) {
+    let first = idx as usize;
+    let pages = idx.size() / PAGE_SIZE;
+    let mut slots = SLOTS.lock();
+    let mut kernel = KERNAL_TABLE.lock();
+    let vaddr = idx.vaddr().into();
+    let res = unsafe {
+        match kernel.as_mut() {
+            Some(table) => table.unmap(vaddr, idx.size(), true, &mut Allocator),
+            None => boot_table().unmap(vaddr, idx.size(), true, &mut PoolAccess),
+        }
+    };
+    // 槽位只用页映射，不会遇到大页
+    res.unwrap_or_else(|e| panic!("clear fixmap {idx:?} failed: {e}"));
+    slots[first..first + pages].fill(None);
+}
+
+/// 在新建的内核页表上重建 fixmap 映射
+pub(crate) fn remap(
+    table: &mut Table<'static>,
+    access: &mut impl Access,
+) -> Result<(), PagingError> {
+    let slots = SLOTS.lock();
+    for (i, slot) in slots.iter().enumerate() {
+        let Some((paddr, cache)) = *slot else {
+            continue;
+        };
+        let vaddr = layout().fixmap.start + i * PAGE_SIZE;
+        unsafe { table.map(map_config(slot_config(vaddr, paddr, cache), false), access)? };
+    }
+    Ok(())
+}
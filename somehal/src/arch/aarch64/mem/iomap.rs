@@ -0,0 +1,194 @@
+//! 设备内存映射，虚拟地址为 `paddr + kliner_offset`。
+//!
+//! 按页计数，重叠的映射共用页表项，最后一个 [`iounmap`] 时才解除映射。
+
+use alloc::collections::BTreeMap;
+use core::{fmt, ops::Range, ptr::NonNull};
+
+use kdef_pgtable::PAGE_SIZE;
+use num_align::NumAlign;
+use page_table_generic::PagingError;
+use spin::Mutex;
+
+use super::mmu::{Allocator, KERNAL_TABLE, map_config};
+use crate::common::mem::{AccessKind, CacheKind, MapRangeConfig, is_ram, kliner_offset};
+
+struct IoPage {
+    count: usize,
+    cache: CacheKind,
+    /// 启动时建立的映射，计数归零也不解除
+    pinned: bool,
+}
+
+/// [`iomap`] 系列的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoMapError {
+    /// 页已以另一种内存类型映射
+    MemoryTypeConflict {
+        paddr: usize,
+        existing: CacheKind,
+        requested: CacheKind,
+    },
+    /// 页是 RAM，已在线性映射中
+    Ram { paddr: usize },
+    /// 页表操作失败
+    Paging(PagingError),
+}
+
+impl fmt::Display for IoMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MemoryTypeConflict {
+                paddr,
+                existing,
+                requested,
+            } => write!(
+                f,
+                "iomap {paddr:#x} as {requested:?}: already mapped as {existing:?}"
+            ),
+            Self::Ram { paddr } => write!(f, "iomap {paddr:#x}: address is RAM"),
+            Self::Paging(e) => write!(f, "iomap: {e}"),
+        }
+    }
+}
+
+impl From<PagingError> for IoMapError {
+    fn from(e: PagingError) -> Self {
+        Self::Paging(e)
+    }
+}
+
+/// 已映射的页，键为物理地址
+static PAGES: Mutex<BTreeMap<usize, IoPage>> = Mutex::new(BTreeMap::new());
+
+fn page_range(phys: usize, size: usize) -> Range<usize> {
+    phys.align_down(PAGE_SIZE)..(phys + size.max(1)).align_up(PAGE_SIZE)
+}
+
+/// 启动时已映射的页（调试串口），永远不会被 [`iounmap`] 解除
+pub(crate) fn pin(paddr: usize, cache: CacheKind) {
+    let mut pages = PAGES.lock();
+    let page = pages.entry(paddr.align_down(PAGE_SIZE)).or_insert(IoPage {
+        count: 0,
+        cache,
+        pinned: true,
+    });
+    page.pinned = true;
+}
+
+/// 以 Device-nGnRE 映射设备内存，`phys`、`size` 不必对齐，返回 `phys` 对应的虚拟地址。
+pub fn iomap(phys: usize, size: usize) -> Result<NonNull<u8>, IoMapError> {
+    iomap_with(phys, size, CacheKind::Device)
+}
+
+/// 以 Device-nGnRnE 映射，写操作要等设备应答
+pub fn iomap_strict(phys: usize, size: usize) -> Result<NonNull<u8>, IoMapError> {
+    iomap_with(phys, size, CacheKind::DeviceStrict)
+}
+
+/// 以 Normal-NC 映射，适合帧缓冲、PCIe 可预取 BAR
+pub fn iomap_wc(phys: usize, size: usize) -> Result<NonNull<u8>, IoMapError> {
+    iomap_with(phys, size, CacheKind::WriteCombining)
+}
+
+/// 以 `cache` 映射 `[phys, phys + size)`。
+///
+/// 与已有映射重叠的页只增加计数，内存类型不同时返回
+/// [`IoMapError::MemoryTypeConflict`]，是 RAM 时返回 [`IoMapError::Ram`]。
+pub fn iomap_with(phys: usize, size: usize, cache: CacheKind) -> Result<NonNull<u8>, IoMapError> {
+    let range = page_range(phys, size);
+    let mut pages = PAGES.lock();
+    for paddr in range.clone().step_by(PAGE_SIZE) {
+        match pages.get(&paddr) {
+            Some(page) if page.cache != cache => {
+                return Err(IoMapError::MemoryTypeConflict {
+                    paddr,
+                    existing: page.cache,
+                    requested: cache,
+                });
+            }
+            None if is_ram(paddr) => return Err(IoMapError::Ram { paddr }),
+            _ => {}
+        }
+    }
+
+    let mut mapped = range.start;
+    let result: Result<(), PagingError> = range.clone().step_by(PAGE_SIZE).try_for_each(|paddr| {
+        if !pages.contains_key(&paddr) {
+            map_page(paddr, cache)?;
+        }
+        mapped = paddr + PAGE_SIZE;
+        Ok(())
+    });
+    if let Err(e) = result {
+        for paddr in (range.start..mapped).step_by(PAGE_SIZE) {
+            if !pages.contains_key(&paddr) {
+                unmap_page(paddr);
+            }
+        }
+        return Err(e.into());
+    }
+
+    for paddr in range.step_by(PAGE_SIZE) {
+        pages
+            .entry(paddr)
+            .or_insert(IoPage {
+                count: 0,
+                cache,
+                pinned: false,
+            })
+            .count += 1;
+    }
+    Ok(NonNull::new((phys + kliner_offset()) as *mut u8).unwrap())
+}
+
+/// 解除 [`iomap`] 系列建立的映射，`vaddr`、`size` 与映射时相同。启动时建立的映射
+/// （调试串口）只减少计数，不会解除。
+///
+/// # Panics
+/// 范围内有未映射的页，或页的计数已为 0 时 panic。
+pub fn iounmap(vaddr: NonNull<u8>, size: usize) {
+    let phys = vaddr.as_ptr() as usize - kliner_offset();
+    let range = page_range(phys, size);
+    let mut pages = PAGES.lock();
+    if let Some(paddr) = range
+        .clone()
+        .step_by(PAGE_SIZE)
+        .find(|p| pages.get(p).is_none_or(|page| page.count == 0))
+    {
+        panic!("iounmap: {paddr:#x} is not mapped");
+    }
+    for paddr in range.step_by(PAGE_SIZE) {
+        let page = pages.get_mut(&paddr).unwrap();
+        page.count -= 1;
+        if page.count == 0 && !page.pinned {
+            pages.remove(&paddr);
+            unmap_page(paddr);
+        }
+    }
+}
+
+fn map_page(paddr: usize, cache: CacheKind) -> Result<(), PagingError> {
+    let mut g = KERNAL_TABLE.lock();
+    let table = g.as_mut().expect("MMU not initialized");
+    let config = MapRangeConfig {
+        vaddr: (paddr + kliner_offset()) as *mut u8,
+        paddr,
+        size: PAGE_SIZE,
+        name: "iomap",
+        cache,
+        access: AccessKind::ReadWrite,
+        cpu_share: false,
+        max_page_size: Some(PAGE_SIZE),
+        min_page_size: None,
+    };
+    unsafe { table.map(map_config(config, true), &mut Allocator) }
+}
+
+fn unmap_page(paddr: usize) {
+    let mut g = KERNAL_TABLE.lock();
+    let table = g.as_mut().expect("MMU not initialized");
+    let vaddr = paddr + kliner_offset();
+    unsafe { table.unmap(vaddr.into(), PAGE_SIZE, true, &mut Allocator) }
+        .unwrap_or_else(|e| panic!("iounmap {vaddr:#x} failed: {e}"));
+}
@@ -1,8 +1,10 @@
 use core::ops::{Deref, DerefMut};
 
 use aarch64_cpu::registers::*;
-use aarch64_cpu_ext::structures::tte::{AccessPermission, Shareability, TTE4K48};
-use kdef_pgtable::KLINER_OFFSET;
+use aarch64_cpu_ext::structures::tte::{
+    AccessPermission, Granule, Granule4KB, Granule16KB, Granule64KB, OA48, Shareability, TTE64,
+};
+use kdef_pgtable::{PAGE_SHIFT, PAGE_SIZE};
 use log::debug;
 use page_table_generic::{
     Access, MapConfig, PTEGeneric, PageTableRef, PhysAddr, TableGeneric, VirtAddr,
@@ -12,36 +14,34 @@ use spin::Mutex;
 
 use crate::{
     arch::el::flush_tlb,
+    boot_info,
     common::{
         self,
-        mem::{AccessKind, MapRangeConfig, regions_to_map},
+        mem::{
+            AccessKind, MapRangeConfig, alloc_frames, free_frames, kliner_offset, order_of,
+            percpu_stacks_to_map, regions_to_map, va_layout,
+        },
     },
     mem::PageTable,
 };
 
-struct Allocator;
+pub(crate) struct Allocator;
 
 impl Access for Allocator {
     unsafe fn alloc(
         &mut self,
         layout: core::alloc::Layout,
     ) -> Option<page_table_generic::PhysAddr> {
-        let ptr = unsafe { alloc::alloc::alloc(layout) };
-        if ptr.is_null() {
-            None
-        } else {
-            let phys = ptr as usize - KLINER_OFFSET;
-            Some(phys.into())
-        }
+        let order = order_of(layout.size().max(layout.align()));
+        alloc_frames(order).map(Into::into)
     }
 
     unsafe fn dealloc(&mut self, ptr: page_table_generic::PhysAddr, layout: core::alloc::Layout) {
-        let ptr = (ptr.raw() + KLINER_OFFSET) as *mut u8;
-        unsafe { alloc::alloc::dealloc(ptr, layout) };
+        free_frames(ptr.raw(), order_of(layout.size().max(layout.align())));
     }
 
     fn phys_to_mut(&self, phys: page_table_generic::PhysAddr) -> *mut u8 {
-        (phys.raw() + KLINER_OFFSET) as *mut u8
+        (phys.raw() + kliner_offset()) as *mut u8
     }
 }
 
@@ -65,6 +65,9 @@ impl From<common::mem::CacheKind> for CacheKind {
             common::mem::CacheKind::Device => CacheKind::Device,
             common::mem::CacheKind::Normal => CacheKind::Normal,
             common::mem::CacheKind::NoCache => CacheKind::NoCache,
+            common::mem::CacheKind::WriteCombining => CacheKind::WriteCombining,
+            common::mem::CacheKind::WriteThrough => CacheKind::WriteThrough,
+            common::mem::CacheKind::DeviceStrict => CacheKind::DeviceStrict,
         }
     }
 }
@@ -81,26 +84,37 @@ impl From<MapRangeConfig> for Tte {
         });
         tte.set_access_permission(value.access.into());
         tte.set_attr_index(CacheKind::from(value.cache).mair_idx());
-        match value.access {
-            AccessKind::Read | AccessKind::ReadWrite => tte.set_executable(false),
-            _ => {}
-        }
+        tte.set_kernel_executable(matches!(
+            value.access,
+            AccessKind::ReadExecute | AccessKind::ReadWriteExecute
+        ));
         tte
     }
 }
 
+pub(crate) fn map_config(region: MapRangeConfig, flush: bool) -> MapConfig<Tte> {
+    let vaddr = region.vaddr.into();
+    let paddr = region.paddr.into();
+    let size = region.size;
+    let min_page_size = region.min_page_size.unwrap_or(0);
+    let max_page_size = region.max_page_size.unwrap_or(usize::MAX);
+    MapConfig::new(vaddr, paddr, size, region.into(), true, flush)
+        .with_page_sizes::<TableImpl>(min_page_size, max_page_size)
+}
+
 pub fn new_table<'a>(
     access: &mut impl Access,
 ) -> Result<Table<'a>, page_table_generic::PagingError> {
-    PageTableRef::create_empty(access)
+    PageTableRef::new_with_level(va_layout().page_levels, access)
 }
 
 pub(crate) fn init_mmu() {
     let mut alloc = Allocator {};
     let access = &mut alloc;
-    let mut table = Table::create_empty(access).unwrap();
+    let mut table = new_table(access).unwrap();
 
-    for region in regions_to_map() {
+    for region in regions_to_map().into_iter().chain(percpu_stacks_to_map()) {
+        let name = region.name;
         unsafe {
             debug!(
                 "Map `{:<12}`: {:?} | [{:#p}, {:#p}) -> [{:#x}, {:#x})",
@@ -113,24 +127,31 @@ pub(crate) fn init_mmu() {
             );
 
             table
-                .map(
-                    MapConfig::new(
-                        region.vaddr.into(),
-                        region.paddr.into(),
-                        region.size,
-                        region.into(),
-                        true,
-                        false,
-                    ),
-                    access,
-                )
-                .unwrap()
+                .map(map_config(region, false), access)
+                .unwrap_or_else(|e| panic!("Map `{name}` failed: {e}"))
         };
     }
+    super::fixmap::remap(&mut table, access).unwrap_or_else(|e| panic!("Map fixmap failed: {e}"));
+    if let Some(d) = &boot_info().debug_console {
+        super::iomap::pin(d.base_phys, common::mem::CacheKind::Device);
+    }
+    check_wx(&table);
     let addr = table.paddr().raw();
     KERNAL_TABLE.lock().replace(table);
 
     debug!("MMU initialized with table at {addr:#x}");
+    set_ttbr(addr);
+}
+
+/// 从核在 loader 的页表上启动，切换到 [`KERNAL_TABLE`] 后栈的守护页才生效
+pub(crate) fn switch_to_kernel_table() {
+    let Some(addr) = KERNAL_TABLE.lock().as_ref().map(|t| t.paddr().raw()) else {
+        return;
+    };
+    set_ttbr(addr);
+}
+
+fn set_ttbr(addr: usize) {
     if CurrentEL.read(CurrentEL::EL) == 1 {
         TTBR1_EL1.set_baddr(addr as _);
         TTBR0_EL1.set_baddr(0);
@@ -140,6 +161,44 @@ pub(crate) fn init_mmu() {
     flush_tlb(None);
 }
 
+/// 查 [`KERNAL_TABLE`]，MMU 未初始化或未映射时返回 `None`
+pub(crate) fn translate(vaddr: usize) -> Option<usize> {
+    let g = KERNAL_TABLE.lock();
+    let table = g.as_ref()?;
+    table
+        .translate(vaddr.into(), &Allocator {})
+        .map(|paddr| paddr.raw())
+}
+
+/// 检查没有既可写又可执行的映射，且内核镜像没有线性映射的别名
+fn check_wx(table: &Table<'static>) {
+    let mut leaves = 0;
+    for info in table.iter_all(&Allocator) {
+        if !(info.level == 1 || info.pte.is_huge()) {
+            continue;
+        }
+        leaves += 1;
+        let pte = info.pte;
+        if pte.is_writable() && pte.is_kernel_executable() {
+            panic!(
+                "W^X violation: {:#x} at level {} is writable and executable",
+                info.vaddr.raw(),
+                info.level
+            );
+        }
+        #[cfg(not(feature = "hv"))]
+        if pte.is_executable() {
+            panic!("{:#x} is executable by EL0", info.vaddr.raw());
+        }
+    }
+
+    let kimage_alias = boot_info().kimage_start_lma as usize + kliner_offset();
+    if let Some(paddr) = table.translate(kimage_alias.into(), &Allocator) {
+        panic!("Kernel image aliased in the linear map: {kimage_alias:#x} -> {paddr:?}");
+    }
+    debug!("W^X check passed, {leaves} leaf entries");
+}
+
 pub fn mmap(region: MapRangeConfig) -> Result<(), page_table_generic::PagingError> {
     let mut g = KERNAL_TABLE.lock();
     let table = g.as_mut().expect("MMU not initialized");
@@ -156,17 +215,7 @@ pub fn mmap(region: MapRangeConfig) -> Result<(), page_table_generic::PagingErro
             region.paddr + region.size
         );
 
-        table.map(
-            MapConfig::new(
-                region.vaddr.into(),
-                region.paddr.into(),
-                region.size,
-                region.into(),
-                true,
-                true,
-            ),
-            access,
-        )
+        table.map(map_config(region, true), access)
     }
 }
 
@@ -175,7 +224,8 @@ pub fn table_map(
     access: &mut impl Access,
     region: MapRangeConfig,
 ) -> Result<(), page_table_generic::PagingError> {
-    let mut table: PageTableRef<'_, TableImpl> = PageTableRef::root_from_addr(table.addr.into());
+    let mut table: PageTableRef<'_, TableImpl> =
+        PageTableRef::from_addr(table.addr.into(), va_layout().page_levels);
 
     unsafe {
         debug!(
@@ -188,27 +238,40 @@ pub fn table_map(
             region.paddr + region.size
         );
 
-        table.map(
-            MapConfig::new(
-                region.vaddr.into(),
-                region.paddr.into(),
-                region.size,
-                region.into(),
-                true,
-                true,
-            ),
-            access,
-        )
+        table.map(map_config(region, true), access)
     }
 }
 
+#[doc(hidden)]
+pub struct PageShift<const N: usize>;
+
+#[doc(hidden)]
+pub trait SelectGranule {
+    type G: Granule;
+}
+
+impl SelectGranule for PageShift<12> {
+    type G = Granule4KB;
+}
+
+impl SelectGranule for PageShift<14> {
+    type G = Granule16KB;
+}
+
+impl SelectGranule for PageShift<16> {
+    type G = Granule64KB;
+}
+
+/// Descriptor of the granule selected by `kdef-pgtable`.
+pub type RawTte = TTE64<<PageShift<PAGE_SHIFT> as SelectGranule>::G, OA48>;
+
 #[repr(transparent)]
 #[derive(Clone, Copy)]
-pub struct Tte(TTE4K48);
+pub struct Tte(RawTte);
 
 impl Tte {
     pub fn empty() -> Self {
-        let mut tte = TTE4K48::new_table(0);
+        let mut tte = RawTte::new_table(0);
         tte.set_is_valid(true);
         tte.set_access();
         tte.set_shareability(Shareability::InnerShareable);
@@ -217,8 +280,36 @@ impl Tte {
     }
 }
 
+impl Tte {
+    /// EL1 有两级特权，内核执行权限由 PXN 控制，用户态始终不可执行（UXN）；
+    /// EL2 只有一级特权，只有 XN。
+    pub fn set_kernel_executable(&mut self, exec: bool) {
+        #[cfg(not(feature = "hv"))]
+        {
+            self.0.set_executable(false);
+            self.0.set_privileged_executable(exec);
+        }
+        #[cfg(feature = "hv")]
+        self.0.set_executable(exec);
+    }
+
+    pub fn is_kernel_executable(&self) -> bool {
+        #[cfg(not(feature = "hv"))]
+        return self.0.is_privileged_executable();
+        #[cfg(feature = "hv")]
+        return self.0.is_executable();
+    }
+
+    pub fn is_writable(&self) -> bool {
+        matches!(
+            self.0.access_permission(),
+            AccessPermission::PrivilegedReadWrite | AccessPermission::ReadWrite
+        )
+    }
+}
+
 impl Deref for Tte {
-    type Target = TTE4K48;
+    type Target = RawTte;
 
     fn deref(&self) -> &Self::Target {
         &self.0
@@ -280,6 +371,12 @@ pub struct TableImpl;
 
 impl TableGeneric for TableImpl {
     type PTE = Tte;
+    const PAGE_SIZE: usize = PAGE_SIZE;
+    // 52-bit VA without LPA2 only exists with 64K pages.
+    const VALID_BITS: usize = if PAGE_SHIFT == 16 { 52 } else { 48 };
+    const LEVEL: usize = kdef_pgtable::page_levels(PAGE_SHIFT, Self::VALID_BITS);
+    // 16K/64K level 1 blocks need a 52-bit output address.
+    const MAX_BLOCK_LEVEL: usize = if PAGE_SHIFT == 12 { 3 } else { 2 };
 
     fn flush(vaddr: Option<VirtAddr>) {
         flush_tlb(vaddr.map(|o| o.raw().into()));
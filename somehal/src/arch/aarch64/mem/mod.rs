@@ -1,29 +1,25 @@
+mod fixmap;
+mod iomap;
 pub mod mmu;
-
-use core::ptr::NonNull;
+mod vmalloc;
 
 pub use page_table_generic::{PhysAddr, VirtAddr};
 
 pub use crate::common::mem::*;
+pub use fixmap::{FIXMAP_FDT_SIZE, FixMap, clear_fixmap, set_fixmap, set_fixmap_range};
+pub use iomap::{IoMapError, iomap, iomap_strict, iomap_wc, iomap_with, iounmap};
+pub use vmalloc::{VmAttrs, vmalloc, vmap, vunmap};
 
 // After GlobalAlloc is implemented, this will be used as the global allocator.
 pub fn init() {
     mmu::init_mmu();
 }
 
-pub fn iomap(phys: usize, size: usize) -> Result<NonNull<u8>, page_table_generic::PagingError> {
-    let vaddr = phys_to_virt(phys);
-    mmu::mmap(MapRangeConfig {
-        vaddr,
-        paddr: phys,
-        size,
-        name: "iomap",
-        cache: CacheKind::Device,
-        access: AccessKind::ReadWrite,
-        cpu_share: false,
-    })?;
-
-    Ok(NonNull::new(vaddr).unwrap())
+/// 虚拟地址对应的物理地址，未映射时返回 `None`。
+///
+/// 内核镜像和线性映射中的 RAM 直接换算，其它地址（iomap、vmalloc、fixmap 等）查页表。
+pub fn virt_to_phys(vaddr: usize) -> Option<usize> {
+    virt_to_phys_fast(vaddr).or_else(|| mmu::translate(vaddr))
 }
 
 pub fn flush_tlb(vaddr: Option<VirtAddr>) {
@@ -0,0 +1,164 @@
+//! vmalloc 区的虚拟地址分配，把不连续的物理页映射到连续的虚拟地址。
+//!
+//! 每块区域后面留一个未映射的保护页，越界访问会触发缺页异常。
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::ptr::NonNull;
+
+use kdef_pgtable::PAGE_SIZE;
+use num_align::{NumAlign, NumAssertAlign};
+use page_table_generic::PagingError;
+use spin::Mutex;
+
+use super::mmu::{Allocator, KERNAL_TABLE, map_config};
+use crate::common::mem::{
+    AccessKind, CacheKind, MapRangeConfig, alloc_frames, free_frames, kliner_offset, va_layout,
+};
+
+/// 区域之间的保护间隔
+const GUARD_SIZE: usize = PAGE_SIZE;
+
+/// [`vmap`] 映射属性
+#[derive(Debug, Clone, Copy)]
+pub struct VmAttrs {
+    pub cache: CacheKind,
+    pub access: AccessKind,
+}
+
+impl VmAttrs {
+    /// 普通内存，可读写
+    pub const KERNEL: Self = Self {
+        cache: CacheKind::Normal,
+        access: AccessKind::ReadWrite,
+    };
+}
+
+struct VmArea {
+    size: usize,
+    /// 由 [`vmalloc`] 分配的物理页，[`vunmap`] 时释放
+    owned: Option<Vec<usize>>,
+}
+
+/// 已分配区域，按起始地址排序
+static AREAS: Mutex<BTreeMap<usize, VmArea>> = Mutex::new(BTreeMap::new());
+
+/// 在 vmalloc 区找一段 `size` 大小的空闲地址，首次适配
+fn alloc_range(areas: &BTreeMap<usize, VmArea>, size: usize) -> Option<usize> {
+    let region = va_layout().vm_layout().vmalloc;
+    let mut cursor = region.start;
+    for (&start, area) in areas {
+        if start - cursor >= size + GUARD_SIZE {
+            break;
+        }
+        cursor = start + area.size + GUARD_SIZE;
+    }
+    let end = cursor.checked_add(size + GUARD_SIZE)?;
+    (end <= region.end()).then_some(cursor)
+}
+
+/// 把物理页 `pages` 依次映射到一段连续的虚拟地址。
+pub fn vmap(pages: &[usize], attrs: VmAttrs) -> Result<NonNull<u8>, PagingError> {
+    map_area(pages, attrs, false)
+}
+
+/// 分配 `size` 大小、虚拟地址连续的内存，物理页不要求连续。
+pub fn vmalloc(size: usize) -> Result<NonNull<u8>, PagingError> {
+    let count = size.max(1).align_up(PAGE_SIZE) / PAGE_SIZE;
+    let mut pages = Vec::with_capacity(count);
+    for _ in 0..count {
+        match alloc_page() {
+            Some(paddr) => pages.push(paddr),
+            None => {
+                free_pages(&pages);
+                return Err(PagingError::NoMemory);
+            }
+        }
+    }
+
+    map_area(&pages, VmAttrs::KERNEL, true).inspect_err(|_| free_pages(&pages))
+}
+
+/// 解除 [`vmap`]/[`vmalloc`] 建立的映射，[`vmalloc`] 分配的物理页一并释放。
+///
+/// # Panics
+/// `vaddr` 不是 [`vmap`]/[`vmalloc`] 返回的地址时 panic。
+pub fn vunmap(vaddr: NonNull<u8>) {
+    let start = vaddr.as_ptr() as usize;
+    let area = AREAS
+        .lock()
+        .remove(&start)
+        .unwrap_or_else(|| panic!("vunmap: {start:#x} is not a vmap area"));
+
+    unmap_range(start, area.size);
+    if let Some(pages) = area.owned {
+        free_pages(&pages);
+    }
+}
+
+fn map_area(pages: &[usize], attrs: VmAttrs, owned: bool) -> Result<NonNull<u8>, PagingError> {
+    if pages.is_empty() {
+        return Err(PagingError::NoMemory);
+    }
+    if let Some(&paddr) = pages.iter().find(|p| !p.is_aligned_to(PAGE_SIZE)) {
+        return Err(PagingError::NotAligned {
+            op: page_table_generic::PagingOp::Map,
+            field: "paddr",
+            vaddr: 0usize.into(),
+            paddr: paddr.into(),
+            align: PAGE_SIZE,
+        });
+    }
+
+    let size = pages.len() * PAGE_SIZE;
+    let mut areas = AREAS.lock();
+    let start = alloc_range(&areas, size).ok_or(PagingError::NoMemory)?;
+
+    if let Err(e) = map_pages(start, pages, attrs) {
+        unmap_range(start, size);
+        return Err(e);
+    }
+
+    let owned = owned.then(|| pages.to_vec());
+    areas.insert(start, VmArea { size, owned });
+    Ok(NonNull::new(start as *mut u8).unwrap())
+}
+
+fn map_pages(start: usize, pages: &[usize], attrs: VmAttrs) -> Result<(), PagingError> {
+    let mut g = KERNAL_TABLE.lock();
+    let table = g.as_mut().expect("MMU not initialized");
+    for (i, &paddr) in pages.iter().enumerate() {
+        let config = MapRangeConfig {
+            vaddr: (start + i * PAGE_SIZE) as *mut u8,
+            paddr,
+            size: PAGE_SIZE,
+            name: "vmalloc",
+            cache: attrs.cache,
+            access: attrs.access,
+            cpu_share: true,
+            max_page_size: Some(PAGE_SIZE),
+            min_page_size: None,
+        };
+        unsafe { table.map(map_config(config, true), &mut Allocator)? };
+    }
+    Ok(())
+}
+
+fn unmap_range(start: usize, size: usize) {
+    let mut g = KERNAL_TABLE.lock();
+    let table = g.as_mut().expect("MMU not initialized");
+    // 区域内只有页映射，不会遇到大页
+    unsafe { table.unmap(start.into(), size, true, &mut Allocator) }
+        .unwrap_or_else(|e| panic!("vunmap {start:#x} failed: {e}"));
+}
+
+fn alloc_page() -> Option<usize> {
+    let paddr = alloc_frames(0)?;
+    unsafe { ((paddr + kliner_offset()) as *mut u8).write_bytes(0, PAGE_SIZE) };
+    Some(paddr)
+}
+
+fn free_pages(pages: &[usize]) {
+    for &paddr in pages {
+        free_frames(paddr, 0);
+    }
+}
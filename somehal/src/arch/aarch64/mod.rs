@@ -4,7 +4,7 @@ use core::{
 };
 
 use aarch64_cpu_ext::cache::{CacheOp, dcache_all};
-use kdef_pgtable::{KLINER_OFFSET, PAGE_SIZE};
+use kdef_pgtable::VaLayout;
 #[cfg(not(feature = "hv"))]
 use pie_boot_loader_aarch64::el1::{set_table, setup_sctlr, setup_table_regs};
 #[cfg(feature = "hv")]
@@ -36,7 +36,13 @@ macro_rules! sym_lma {
 #[cfg_attr(not(feature = "hv"), path = "el1.rs")]
 mod el;
 
-use crate::{boot_info, start_code};
+pub(crate) use el::{percpu_base, set_percpu_base};
+
+use crate::{
+    boot_info,
+    common::cpu::{logical_of, set_logical_id},
+    start_code,
+};
 use aarch64_cpu::{asm::barrier, registers::*};
 use kasm_aarch64::{self as kasm, adr_l};
 use pie_boot_loader_aarch64::EarlyBootArgs;
@@ -45,7 +51,13 @@ use pie_boot_loader_aarch64::EarlyBootArgs;
 static mut UART_DEBUG: usize = 0;
 
 const FLAG_LE: usize = 0b0;
-const FLAG_PAGE_SIZE_4K: usize = 0b10;
+/// Image header `flags[2:1]`: 1 = 4K, 2 = 16K, 3 = 64K.
+const FLAG_PAGE_SIZE: usize = match kdef_pgtable::PAGE_SHIFT {
+    12 => 0b010,
+    14 => 0b100,
+    16 => 0b110,
+    _ => panic!("unsupported page size"),
+};
 const FLAG_ANY_MEM: usize = 0b1000;
 
 #[unsafe(naked)]
@@ -73,7 +85,7 @@ pub unsafe extern "C" fn _start() -> ! {
         ".ascii \"ARM\\x64\"",
         // Another reserved field at the end of the header
         ".byte 0, 0, 0, 0",
-        flags = const FLAG_LE | FLAG_PAGE_SIZE_4K | FLAG_ANY_MEM,
+        flags = const FLAG_LE | FLAG_PAGE_SIZE | FLAG_ANY_MEM,
         entry = sym primary_entry,
     )
 }
@@ -101,23 +113,38 @@ fn preserve_boot_args() {
 	stp	x0,  x1, [x8]			// x0 .. x3 at kernel entry
 	stp	x2,  x3, [x8, #16]
 
-    LDR  x0,  ={virt_entry}
-    str  x0,  [x8, {args_of_entry_vma}]",
+    LDR x0, ={boot_magic}
+    str x0,  [x8, {args_of_magic}]
+
+    // The kernel may be linked with `-pie`, then literal pools hold 0 until
+    // the loader applies `.rela.dyn`. Derive link addresses from the link
+    // base instead: x9 = vma - lma.
+    LDR  x9,  ={kimage_vaddr}",
     adr_l!(x0, "_start"),
     "
     str x0,  [x8, {args_of_kimage_addr_lma}]
+    sub x9,  x9, x0
 
-    LDR  x0,  =_start
+    LDR  x0,  ={kimage_vaddr}
     str x0,  [x8, {args_of_kimage_addr_vma}]",
 
-    adr_l!(x0, "__cpu0_stack_top"),
+    adr_l!(x0, "{virt_entry}"),
     "
-    str x0,  [x8, {args_of_stack_top_lma}]",
+    add  x0,  x0, x9
+    str  x0,  [x8, {args_of_entry_vma}]",
+
+    adr_l!(x0, "__cpu0_stack_top"),
     "
-    LDR x0,  =__cpu0_stack_top
-    str x0,  [x8, {args_of_stack_top_vma}]
-    ",
+    str x0,  [x8, {args_of_stack_top_lma}]
+    add x0,  x0, x9
+    str x0,  [x8, {args_of_stack_top_vma}]",
 
+    adr_l!(x0, "__rela_dyn_start"),
+    "
+    str x0,  [x8, {args_of_rela_start}]",
+    adr_l!(x0, "__rela_dyn_end"),
+    "
+    str x0,  [x8, {args_of_rela_end}]",
 
     adr_l!(x0, "__kernel_code_end"),
     "
@@ -127,11 +154,26 @@ fn preserve_boot_args() {
     mov x0, {el_value}              // Set target EL based on feature
     str x0,  [x8, {args_of_el}]
 
+    mov x0, {page_shift}
+    str x0,  [x8, {args_of_page_shift}]
+
+    mov x0, {va_bits}
+    str x0,  [x8, {args_of_va_bits}]
+
+    mov x0, {page_levels}
+    str x0,  [x8, {args_of_page_levels}]
+
+    LDR x0, ={kimage_vaddr}
+    str x0,  [x8, {args_of_kimage_vaddr}]
+
+    LDR x0, ={kimage_vsize}
+    str x0,  [x8, {args_of_kimage_vsize}]
+
     LDR x0, ={kliner_offset}
     str x0,  [x8, {args_of_kliner_offset}]
 
-    mov x0, {page_size}
-    str x0,  [x8, {args_of_page_size}]
+    mov x0, {va_bits_max}
+    str x0,  [x8, {args_of_va_bits_max}]
 
     mov x0, #1
     str x0,  [x8, {args_of_debug}]
@@ -144,18 +186,32 @@ fn preserve_boot_args() {
         ",
     boot_args = sym crate::BOOT_ARGS,
     virt_entry = sym switch_sp,
+    boot_magic = const pie_boot_if::BOOT_MAGIC,
+    args_of_magic = const offset_of!(EarlyBootArgs, magic),
     args_of_entry_vma = const  offset_of!(EarlyBootArgs, virt_entry),
     args_of_kimage_addr_lma = const  offset_of!(EarlyBootArgs, kimage_addr_lma),
     args_of_kimage_addr_vma = const  offset_of!(EarlyBootArgs, kimage_addr_vma),
     args_of_stack_top_lma = const  offset_of!(EarlyBootArgs, stack_top_lma),
     args_of_stack_top_vma = const  offset_of!(EarlyBootArgs, stack_top_vma),
     args_of_kcode_end = const  offset_of!(EarlyBootArgs, kcode_end),
+    args_of_rela_start = const  offset_of!(EarlyBootArgs, rela_start),
+    args_of_rela_end = const  offset_of!(EarlyBootArgs, rela_end),
     args_of_el = const  offset_of!(EarlyBootArgs, el),
     el_value = const if cfg!(feature = "hv") { 2 } else { 1 },
-    kliner_offset = const KLINER_OFFSET,
-    args_of_kliner_offset = const offset_of!(EarlyBootArgs, kliner_offset),
-    page_size = const PAGE_SIZE,
-    args_of_page_size = const offset_of!(EarlyBootArgs, page_size),
+    page_shift = const VaLayout::LINKED.page_shift,
+    args_of_page_shift = const offset_of!(EarlyBootArgs, layout.page_shift),
+    va_bits = const VaLayout::LINKED.va_bits,
+    args_of_va_bits = const offset_of!(EarlyBootArgs, layout.va_bits),
+    page_levels = const VaLayout::LINKED.page_levels,
+    args_of_page_levels = const offset_of!(EarlyBootArgs, layout.page_levels),
+    kimage_vaddr = const VaLayout::LINKED.kimage_vaddr,
+    args_of_kimage_vaddr = const offset_of!(EarlyBootArgs, layout.kimage_vaddr),
+    kimage_vsize = const VaLayout::LINKED.kimage_vsize,
+    args_of_kimage_vsize = const offset_of!(EarlyBootArgs, layout.kimage_vsize),
+    kliner_offset = const VaLayout::LINKED.kliner_offset,
+    args_of_kliner_offset = const offset_of!(EarlyBootArgs, layout.kliner_offset),
+    va_bits_max = const VaLayout::MAX_VA_BITS,
+    args_of_va_bits_max = const offset_of!(EarlyBootArgs, va_bits_max),
     args_of_debug = const offset_of!(EarlyBootArgs, debug),
     dcache_inval_poc = sym cache::__dcache_inval_poc,
     boot_arg_size = const size_of::<EarlyBootArgs>()
@@ -174,11 +230,11 @@ pub fn _start_secondary(_stack_top: usize) -> ! {
         mov     x0, x20
         bl      {switch_to_elx}
         bl      {enable_fp}
-        bl      {init_mmu} // return va_offset x0
+        bl      {init_mmu} // return kliner_offset x0
         add     sp, sp, x0
 
-        mov     x0, x19                 // call_secondary_main(cpu_id)
-        ldr     x8, =__pie_boot_secondary
+        mov     x0, x19                 // secondary_virt_entry(mpidr)
+        ldr     x8, ={secondary_virt_entry}
         blr     x8
         b      .",
 
@@ -186,9 +242,21 @@ pub fn _start_secondary(_stack_top: usize) -> ! {
         switch_to_elx = sym el::switch_to_elx,
         init_mmu = sym init_mmu,
         enable_fp = sym enable_fp,
+        secondary_virt_entry = sym secondary_virt_entry,
     )
 }
 
+extern "C" fn secondary_virt_entry(mpidr: usize) {
+    unsafe extern "C" {
+        fn __pie_boot_secondary(cpu_id: usize);
+    }
+    mem::mmu::switch_to_kernel_table();
+    let cpu_id = logical_of(mpidr).unwrap_or_else(|| panic!("Unknown CPU {mpidr:#x}"));
+    set_percpu_base(crate::common::percpu::area_of(cpu_id));
+    set_logical_id(cpu_id);
+    unsafe { __pie_boot_secondary(cpu_id) }
+}
+
 #[start_code]
 fn enable_fp() {
     CPACR_EL1.write(CPACR_EL1::FPEN::TrapNothing);
@@ -198,13 +266,15 @@ fn enable_fp() {
 #[start_code]
 fn init_mmu() -> usize {
     dcache_all(CacheOp::Invalidate);
-    setup_table_regs();
+    let layout = &boot_info().va_layout;
+    setup_table_regs(layout.va_bits, layout.page_shift);
 
     let addr = boot_info().pg_start as usize;
     set_table(addr);
     setup_sctlr();
 
-    boot_info().kcode_offset()
+    // 栈可能在其它 NUMA 节点的内存中，只有线性映射覆盖所有 RAM
+    layout.kliner_offset
 }
 
 #[unsafe(naked)]
@@ -6,7 +6,7 @@ use fdt_parser::Fdt;
 use log::debug;
 use smccc::{Hvc, Smc, psci};
 
-use crate::{_start_secondary, boot_info, lazy_static::LazyStatic, println};
+use crate::{_start_secondary, boot_info, common::cpu::mpidr_of, lazy_static::LazyStatic, println};
 
 pub use smccc::psci::error::Error as PsciError;
 
@@ -63,8 +63,9 @@ pub fn shutdown() -> ! {
     }
 }
 
-/// Power on a CPU
+/// Power on a CPU, `cpu_id` is the logical id
 pub fn cpu_on(cpu_id: u64, stack_top: u64) -> Result<(), PsciError> {
+    let mpidr = mpidr_of(cpu_id as _).ok_or(PsciError::InvalidParameters)? as u64;
     unsafe {
         if super::UART_DEBUG == 0 {
             super::UART_DEBUG = boot_info()
@@ -93,7 +94,7 @@ pub fn cpu_on(cpu_id: u64, stack_top: u64) -> Result<(), PsciError> {
     let size = (__bss_stop as usize) - start;
     dcache_range(CacheOp::Clean, start, size);
 
-    _cpu_on(cpu_id, entry as _, stack_top)
+    _cpu_on(mpidr, entry as _, stack_top)
 }
 
 fn _cpu_on(cpu_id: u64, entry: u64, stack_top: u64) -> Result<(), smccc::psci::error::Error> {
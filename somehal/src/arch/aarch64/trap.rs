@@ -1,9 +1,13 @@
 use aarch64_cpu::registers::*;
-use core::arch::{asm, global_asm};
+use core::{
+    arch::{asm, global_asm},
+    sync::atomic::AtomicU32,
+};
 use kasm_aarch64::aarch64_trap_handler;
 use log::*;
 
 use super::context::Context;
+use crate::common::{cpu::cpu_logical_id, mem::guard_page_owner};
 
 #[aarch64_trap_handler(kind = "irq")]
 fn handle_irq(_ctx: &Context) {
@@ -105,12 +109,39 @@ pub fn handle_page_fault(vaddr: usize, reason: PageFaultReason) {
     panic!("Invalid addr fault @{vaddr:#x}, reason: {reason:?}");
 }
 
+const EMERGENCY_STACK_SIZE: usize = 0x4000;
+
+/// 栈溢出时使用，只有第一个溢出的 CPU 能拿到，`u128` 保证 16 字节对齐
+static mut EMERGENCY_STACK: [u128; EMERGENCY_STACK_SIZE / 16] = [0; EMERGENCY_STACK_SIZE / 16];
+static EMERGENCY_LOCK: AtomicU32 = AtomicU32::new(0);
+
+/// 同步异常入口发现栈上保存上下文的位置未映射时，在应急栈上调用，`sp` 为异常时的栈指针
+extern "C" fn handle_stack_overflow(sp: usize) -> ! {
+    let (esr, elr, far, _) = current_exception_state();
+    let owner = [far, sp.wrapping_sub(size_of::<Context>())]
+        .into_iter()
+        .find_map(guard_page_owner);
+    match owner {
+        Some(cpu) => panic!("stack overflow on CPU {cpu}: sp={sp:#x} pc={elr:#x} FAR={far:#x}"),
+        None => panic!(
+            "Kernel stack not mapped on CPU {}: sp={sp:#x} pc={elr:#x} ESR={esr:#x} FAR={far:#x}",
+            cpu_logical_id()
+        ),
+    }
+}
+
 global_asm!(
     include_str!("vectors.s"),
     irq_handler = sym handle_irq,
     fiq_handler = sym handle_fiq,
     sync_handler = sym handle_sync,
     serror_handler = sym handle_serror,
+    overflow_handler = sym handle_stack_overflow,
+    frame_size = const size_of::<Context>(),
+    el = const if cfg!(feature = "hv") { 2 } else { 1 },
+    emergency_lock = sym EMERGENCY_LOCK,
+    emergency_stack = sym EMERGENCY_STACK,
+    emergency_stack_size = const EMERGENCY_STACK_SIZE,
 );
 
 pub fn setup() {
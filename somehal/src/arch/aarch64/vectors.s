@@ -18,7 +18,7 @@ vector_table_el1:
     B .
     .balign 0x80
     curr_el_spx_sync:
-    B {sync_handler}
+    B curr_el_spx_sync_entry
     .balign 0x80
     curr_el_spx_irq: 
 	B {irq_handler}
@@ -57,4 +57,39 @@ vector_table_el1:
     lower_el_aarch32_serror: // The exception handler for the system error
     // exception from a lower EL(AArch32).
 
-// ------------------------------------------------------------
\ No newline at end of file
+// ------------------------------------------------------------
+
+// 保存上下文前先用 AT 检查栈上要写的位置是否已映射，未映射说明栈溢出到了守护页，
+// 换到应急栈处理。somehal 不运行 EL0，SP_EL0 用作临时寄存器。
+.balign 0x800
+curr_el_spx_sync_entry:
+    msr     sp_el0, x0
+    sub     x0, sp, #{frame_size}
+.if {el} == 2
+    at      s1e2w, x0
+.else
+    at      s1e1w, x0
+.endif
+    isb
+    mrs     x0, par_el1
+    tbnz    x0, #0, 1f
+    mrs     x0, sp_el0
+    B {sync_handler}
+1:
+    // 不再返回，x0~x2 可以覆盖，只保留溢出时的 sp
+    mov     x0, sp
+    msr     sp_el0, x0
+    adrp    x0, {emergency_lock}
+    add     x0, x0, :lo12:{emergency_lock}
+2:
+    ldaxr   w1, [x0]
+    cbnz    w1, 2b
+    mov     w1, #1
+    stxr    w2, w1, [x0]
+    cbnz    w2, 2b
+    adrp    x0, {emergency_stack}
+    add     x0, x0, :lo12:{emergency_stack}
+    mov     x1, #{emergency_stack_size}
+    add     sp, x0, x1
+    mrs     x0, sp_el0
+    B {overflow_handler}
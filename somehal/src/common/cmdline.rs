@@ -0,0 +1,196 @@
+//! 内核命令行，来自 `/chosen/bootargs`。
+//!
+//! 参数以空白分隔，形如 `name=value` 或 `name`，值可以用双引号包含空白：
+//! `name="a b"`。`--` 之后的内容不作为内核参数，见 [`init_args`]。
+//!
+//! 用 [`early_param`](crate::early_param) 注册的处理函数在 `virt_entry` 中、进入
+//! `main` 之前运行。除 [`PRE_CONSOLE`] 外都在调试串口初始化之后运行，可以输出信息。
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use log::LevelFilter;
+
+use crate::{boot_info, early_param, println};
+
+/// 一个参数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Param<'a> {
+    pub name: &'a str,
+    /// `name` 形式的开关为 `None`
+    pub value: Option<&'a str>,
+}
+
+/// 参数迭代器，见 [`parse`]
+#[derive(Clone)]
+pub struct Params<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Params<'a> {
+    type Item = Param<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let s = self.rest.trim_start();
+        if s.is_empty() || is_separator(s) {
+            self.rest = "";
+            return None;
+        }
+
+        let mut in_quote = false;
+        let end = s
+            .char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    in_quote = !in_quote;
+                }
+                c.is_whitespace() && !in_quote
+            })
+            .map_or(s.len(), |(i, _)| i);
+        self.rest = &s[end..];
+
+        let token = &s[..end];
+        // 整个参数加引号：`"name=a b"`
+        let token = match token.strip_prefix('"') {
+            Some(t) => t.strip_suffix('"').unwrap_or(t),
+            None => token,
+        };
+        Some(match token.split_once('=') {
+            Some((name, value)) => Param {
+                name,
+                value: Some(unquote(value)),
+            },
+            None => Param {
+                name: token,
+                value: None,
+            },
+        })
+    }
+}
+
+fn is_separator(s: &str) -> bool {
+    s.strip_prefix("--")
+        .is_some_and(|r| r.is_empty() || r.starts_with(char::is_whitespace))
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .map_or(value, |v| v.strip_suffix('"').unwrap_or(v))
+}
+
+/// 解析命令行 `s`
+pub fn parse(s: &str) -> Params<'_> {
+    Params { rest: s }
+}
+
+/// 完整的内核命令行
+pub fn cmdline() -> &'static str {
+    boot_info().cmdline.as_str()
+}
+
+/// 内核命令行中的参数
+pub fn params() -> Params<'static> {
+    parse(cmdline())
+}
+
+/// 查找参数，多次出现时取最后一个
+pub fn get(name: &str) -> Option<Param<'static>> {
+    params().filter(|p| p.name == name).last()
+}
+
+/// 是否有参数 `name`
+pub fn has(name: &str) -> bool {
+    params().any(|p| p.name == name)
+}
+
+/// `--` 之后传给 init 的参数
+pub fn init_args() -> Option<&'static str> {
+    let s = cmdline();
+    let mut rest = s;
+    loop {
+        let trimmed = rest.trim_start();
+        if trimmed.is_empty() {
+            return None;
+        }
+        if is_separator(trimmed) {
+            return Some(trimmed[2..].trim_start());
+        }
+        let mut params = parse(trimmed);
+        params.next()?;
+        rest = params.rest;
+    }
+}
+
+/// 早期参数，由 [`early_param`](crate::early_param) 生成，放在 `.early_param` 段
+pub struct EarlyParam {
+    pub name: &'static str,
+    pub handler: fn(Option<&'static str>),
+}
+
+fn early_params() -> &'static [EarlyParam] {
+    unsafe extern "C" {
+        fn __early_param_start();
+        fn __early_param_end();
+    }
+    let start = __early_param_start as usize;
+    let len = (__early_param_end as usize - start) / size_of::<EarlyParam>();
+    unsafe { core::slice::from_raw_parts(start as *const EarlyParam, len) }
+}
+
+/// 在调试串口初始化之前运行的早期参数，它们的输出会丢失
+pub const PRE_CONSOLE: &[&str] = &["earlycon"];
+
+/// 按命令行中的顺序调用早期参数的处理函数。`pre_console` 为 `true` 时只调用
+/// [`PRE_CONSOLE`] 中的参数，否则调用其余的
+pub(crate) fn run_early_params(pre_console: bool) {
+    let handlers = early_params();
+    for param in params() {
+        for h in handlers
+            .iter()
+            .filter(|h| h.name == param.name && PRE_CONSOLE.contains(&h.name) == pre_console)
+        {
+            (h.handler)(param.value);
+        }
+    }
+}
+
+/// `LevelFilter` 的序号，`u8::MAX` 表示未设置
+static LOGLEVEL: AtomicU8 = AtomicU8::new(u8::MAX);
+
+/// `loglevel=` 指定的日志级别。
+///
+/// 处理时已调用 [`log::set_max_level`]，之后自行设置级别的 logger 应以此为准。
+pub fn loglevel() -> Option<LevelFilter> {
+    const LEVELS: [LevelFilter; 6] = [
+        LevelFilter::Off,
+        LevelFilter::Error,
+        LevelFilter::Warn,
+        LevelFilter::Info,
+        LevelFilter::Debug,
+        LevelFilter::Trace,
+    ];
+    LEVELS
+        .get(LOGLEVEL.load(Ordering::Relaxed) as usize)
+        .copied()
+}
+
+/// 与 Linux 相同，只输出级别数值小于 `loglevel` 的消息
+#[early_param("loglevel")]
+fn set_loglevel(value: Option<&str>) {
+    let Some(level) = value.and_then(|v| v.parse::<u8>().ok()) else {
+        println!("invalid `loglevel={}`, ignored", value.unwrap_or(""));
+        return;
+    };
+    let filter = match level {
+        0 => LevelFilter::Off,
+        // KERN_EMERG ~ KERN_ERR
+        1..=4 => LevelFilter::Error,
+        // KERN_WARNING、KERN_NOTICE
+        5..=6 => LevelFilter::Warn,
+        7 => LevelFilter::Info,
+        8 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    LOGLEVEL.store(filter as u8, Ordering::Relaxed);
+    log::set_max_level(filter);
+}
@@ -1,4 +1,64 @@
-use crate::lazy_static::LazyStatic;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::{boot_info, early_param, lazy_static::LazyStatic, percpu};
+
+/// 支持的最大 CPU 数
+pub const MAX_CPUS: usize = 256;
 
 #[unsafe(link_section = ".data")]
 pub static CPU_NUM: LazyStatic<usize> = LazyStatic::with_default(1);
+
+/// 逻辑 id 对应的 MPIDR，启动 CPU 为 0，其余按设备树中的顺序
+#[unsafe(link_section = ".data")]
+static mut HW_IDS: [usize; MAX_CPUS] = [0; MAX_CPUS];
+
+#[percpu]
+static LOGICAL_ID: AtomicUsize = AtomicUsize::new(0);
+
+static NOSMP: AtomicBool = AtomicBool::new(false);
+
+/// 命令行中有 `nosmp`，只使用启动 CPU
+pub(crate) fn nosmp() -> bool {
+    NOSMP.load(Ordering::Relaxed)
+}
+
+#[early_param("nosmp")]
+fn set_nosmp(_value: Option<&str>) {
+    NOSMP.store(true, Ordering::Relaxed);
+}
+
+/// 为启动 CPU 和 `hw_ids` 分配逻辑 id，并设置 [`CPU_NUM`]
+pub(crate) fn init_logical_ids(hw_ids: impl Iterator<Item = usize>) {
+    let boot = boot_info().cpu_id;
+    let mut num = 0;
+    for id in [boot].into_iter().chain(hw_ids.filter(|&id| id != boot)) {
+        assert!(num < MAX_CPUS, "Too many CPUs, max {MAX_CPUS}");
+        unsafe { (&raw mut HW_IDS).cast::<usize>().add(num).write(id) };
+        num += 1;
+    }
+    CPU_NUM.init(num);
+}
+
+fn hw_ids() -> &'static [usize] {
+    unsafe { core::slice::from_raw_parts((&raw const HW_IDS).cast(), *CPU_NUM) }
+}
+
+/// 从核进入 `#[secondary_entry]` 前设置
+pub(crate) fn set_logical_id(id: usize) {
+    LOGICAL_ID.this_cpu().store(id, Ordering::Relaxed);
+}
+
+/// 当前 CPU 的逻辑 id
+pub fn cpu_logical_id() -> usize {
+    LOGICAL_ID.this_cpu().load(Ordering::Relaxed)
+}
+
+/// 逻辑 id 对应的 MPIDR 亲和性值
+pub fn mpidr_of(logical: usize) -> Option<usize> {
+    hw_ids().get(logical).copied()
+}
+
+/// MPIDR 亲和性值对应的逻辑 id
+pub fn logical_of(mpidr: usize) -> Option<usize> {
+    hw_ids().iter().position(|&id| id == mpidr)
+}
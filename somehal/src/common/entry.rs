@@ -1,4 +1,4 @@
-use pie_boot_if::BootInfo;
+use pie_boot_if::{BOOT_MAGIC, BootInfo};
 
 use crate::{common, lazy_static::LazyStatic, power, println, setup_exception_vectors};
 
@@ -14,23 +14,35 @@ pub(crate) unsafe fn boot_info_edit<R>(f: impl FnOnce(&mut BootInfo) -> R) -> R
 }
 
 pub fn virt_entry(args: &BootInfo) {
+    // 版本不匹配时其它字段都不可信，串口也还没有初始化，只能停下
+    assert_eq!(
+        args.magic, BOOT_MAGIC,
+        "loader and kernel versions mismatch"
+    );
     common::mem::clean_bss();
+    crate::arch::set_percpu_base(common::percpu::boot_area());
     BOOT_INFO.init(args.clone());
+    common::cmdline::run_early_params(true);
     common::fdt::init_debugcon(boot_info().fdt);
+    common::cmdline::run_early_params(false);
     println!("SomeHAL booting...");
     setup_exception_vectors();
     power::init_by_fdt(boot_info().fdt);
     common::fdt::setup_plat_info();
     common::mem::init_regions(&args.memory_regions);
+    // 先于 CPU 栈和早期堆，以免它们占掉 `alloc-ranges` 内的内存
+    common::fdt::alloc_reserved_dynamic();
 
     unsafe {
-        BOOT_INFO.edit(|info| info.free_memory_start = common::mem::init_percpu_stack());
+        common::mem::init_percpu_stack();
+        common::mem::init_early_heap();
 
-        // 合并和去重内存区域（按类型单独处理）
-        common::mem::merge_and_dedup_regions();
+        common::mem::init_usable();
+        common::mem::init_frames();
 
-        let (region_ptr, region_len) =
-            common::mem::with_regions(|regions| (regions.as_mut_ptr(), regions.len()));
+        let (region_ptr, region_len) = common::mem::with_regions(|regions| {
+            (regions.storage_mut().as_mut_ptr(), regions.len())
+        });
         let region_slice = core::slice::from_raw_parts_mut(region_ptr, region_len);
         BOOT_INFO.edit(|info| info.memory_regions = region_slice.into());
 
@@ -0,0 +1,176 @@
+//! 限制内核可用的 RAM：`/chosen` 的 `linux,usable-memory-range` 和命令行中的
+//! `mem=`、`memmap=`。在 RAM 加入区域表之前裁剪，不在范围内的 RAM 不会被映射和上报。
+
+use core::ops::Range;
+
+use fdt_parser::Fdt;
+use heapless::Vec;
+use pie_boot_if::{MemoryRegion, MemoryRegionKind};
+
+use spin::Mutex;
+
+use super::read_cells;
+use crate::{early_param, println};
+
+const MAX_RANGES: usize = 16;
+
+#[derive(Clone)]
+pub(super) struct MemLimits {
+    /// `linux,usable-memory-range`，为空表示不限制
+    usable: Vec<Range<usize>, MAX_RANGES>,
+    /// `mem=`，RAM 总量上限
+    mem: Option<usize>,
+    /// `memmap=exactmap`，忽略设备树中的 RAM
+    exactmap: bool,
+    /// `memmap=` 给出的区域
+    memmap: Vec<MemoryRegion, MAX_RANGES>,
+}
+
+/// 命令行给出的限制，由早期参数填写
+static CMDLINE_LIMITS: Mutex<MemLimits> = Mutex::new(MemLimits::new());
+
+#[early_param("mem")]
+fn set_mem(value: Option<&str>) {
+    match value.and_then(parse_size) {
+        Some((size, "")) => CMDLINE_LIMITS.lock().mem = Some(size),
+        _ => {
+            println!("invalid `mem={}`, ignored", value.unwrap_or(""));
+        }
+    }
+}
+
+#[early_param("memmap")]
+fn set_memmap(value: Option<&str>) {
+    CMDLINE_LIMITS.lock().parse_memmap(value.unwrap_or(""));
+}
+
+impl MemLimits {
+    const fn new() -> Self {
+        Self {
+            usable: Vec::new(),
+            mem: None,
+            exactmap: false,
+            memmap: Vec::new(),
+        }
+    }
+
+    pub(super) fn from_fdt(fdt: &Fdt<'static>) -> Self {
+        let mut limits = CMDLINE_LIMITS.lock().clone();
+        let Some(chosen) = fdt.find_nodes("/chosen").next() else {
+            return limits;
+        };
+
+        if let Some(prop) = chosen.find_property("linux,usable-memory-range") {
+            let root = fdt.all_nodes().next();
+            let cells = |name, default| {
+                root.as_ref()
+                    .and_then(|n| n.find_property(name))
+                    .map_or(default, |p| p.u32() as usize)
+            };
+            let (addr_cells, size_cells) = (cells("#address-cells", 2), cells("#size-cells", 1));
+            let mut values = prop.u32_list();
+            while let (Some(start), Some(size)) = (
+                read_cells(&mut values, addr_cells),
+                read_cells(&mut values, size_cells),
+            ) {
+                if size > 0 && limits.usable.push(start..start + size).is_err() {
+                    println!("linux,usable-memory-range: too many ranges, the rest ignored");
+                    break;
+                }
+            }
+            limits.usable.sort_unstable_by_key(|r| r.start);
+        }
+
+        limits
+    }
+
+    /// `exactmap`、`nn@ss`（RAM）、`nn$ss`/`nn!ss`/`nn#ss`（保留），多项用 `,` 分隔
+    fn parse_memmap(&mut self, value: &str) {
+        for item in value.split(',') {
+            if item == "exactmap" {
+                self.exactmap = true;
+                continue;
+            }
+            let region = parse_size(item).and_then(|(size, rest)| {
+                let mut chars = rest.chars();
+                let kind = match chars.next()? {
+                    '@' => MemoryRegionKind::Ram,
+                    '$' | '!' | '#' => MemoryRegionKind::Reserved,
+                    _ => return None,
+                };
+                let (start, "") = parse_size(chars.as_str())? else {
+                    return None;
+                };
+                Some(MemoryRegion {
+                    start,
+                    end: start + size,
+                    kind,
+                })
+            });
+            match region {
+                Some(r) if self.memmap.push(r).is_ok() => {}
+                Some(_) => {
+                    println!("`memmap={value}`: too many entries, `{item}` ignored");
+                }
+                None => {
+                    println!("invalid `memmap={value}`, `{item}` ignored");
+                }
+            }
+        }
+    }
+
+    /// 按限制裁剪 RAM，`banks` 须按地址排序
+    pub(super) fn apply(&self, banks: &[Range<usize>], mut add: impl FnMut(MemoryRegion)) {
+        let mut budget = self.mem.unwrap_or(usize::MAX);
+        if !self.exactmap {
+            for bank in banks {
+                let mut clip = |range: Range<usize>| {
+                    let size = range.len().min(budget);
+                    budget -= size;
+                    if size > 0 {
+                        add(MemoryRegion {
+                            start: range.start,
+                            end: range.start + size,
+                            kind: MemoryRegionKind::Ram,
+                        });
+                    }
+                };
+                if self.usable.is_empty() {
+                    clip(bank.clone());
+                    continue;
+                }
+                for usable in &self.usable {
+                    let start = bank.start.max(usable.start);
+                    let end = bank.end.min(usable.end);
+                    if start < end {
+                        clip(start..end);
+                    }
+                }
+            }
+        }
+        for &region in &self.memmap {
+            add(region);
+        }
+    }
+}
+
+/// 解析 `512M`、`0x80000000` 这样的大小，返回剩余部分
+fn parse_size(s: &str) -> Option<(usize, &str)> {
+    let (radix, digits) = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => (16, hex),
+        None => (10, s),
+    };
+    let len = digits
+        .find(|c: char| !c.is_digit(radix))
+        .unwrap_or(digits.len());
+    let value = usize::from_str_radix(&digits[..len], radix).ok()?;
+    let rest = &digits[len..];
+    let shift = match rest.chars().next() {
+        Some('K' | 'k') => 10,
+        Some('M' | 'm') => 20,
+        Some('G' | 'g') => 30,
+        Some('T' | 't') => 40,
+        _ => return Some((value, rest)),
+    };
+    Some((value.checked_mul(1 << shift)?, &rest[1..]))
+}
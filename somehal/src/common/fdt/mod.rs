@@ -1,13 +1,24 @@
-use core::ptr::NonNull;
+use core::{
+    ops::Range,
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+mod limit;
+mod numa;
 
-use fdt_parser::{Fdt, Status};
-use pie_boot_if::{MemoryRegion, MemoryRegionKind};
+use fdt_parser::{Fdt, Node, Status};
 
 use crate::{
     boot_info,
-    common::{self, cpu::CPU_NUM},
+    common::{
+        self,
+        cpu::{init_logical_ids, nosmp},
+    },
+    early_param,
     lazy_static::LazyStatic,
-    mem::phys_to_virt,
+    mem::{ReservedMem, phys_to_virt},
+    println,
 };
 
 #[unsafe(link_section = ".data")]
@@ -18,19 +29,40 @@ static RX: LazyStatic<any_uart::Receiver> = LazyStatic::new();
 fn debug_uart_phys_to_virt(p: usize) -> *mut u8 {
     // 如果bootloader已经设置了debug console，说明虚拟地址已经映射好了
     if boot_info().debug_console.is_some() {
-        (p + crate::KLINER_OFFSET) as _
+        (p + crate::mem::kliner_offset()) as _
     } else {
         phys_to_virt(p)
     }
 }
 
+static EARLYCON_OFF: AtomicBool = AtomicBool::new(false);
+
+/// `earlycon=off` 或 `earlycon=none` 关闭早期串口。
+///
+/// 串口只来自 `/chosen/stdout-path`，不支持 `earlycon=uart8250,mmio32,<addr>`、`earlycon=pl011,<addr>`
+/// 等指定串口的写法，这类值在串口初始化后报告并忽略。
+#[early_param("earlycon")]
+fn set_earlycon(value: Option<&str>) {
+    if matches!(value, Some("off" | "none")) {
+        EARLYCON_OFF.store(true, Ordering::Relaxed);
+    }
+}
+
 pub(crate) fn init_debugcon(fdt: Option<NonNull<u8>>) -> Option<()> {
+    if EARLYCON_OFF.load(Ordering::Relaxed) {
+        return None;
+    }
     let uart = any_uart::init(fdt?, debug_uart_phys_to_virt)?;
     TX.init(uart.tx?);
     RX.init(uart.rx?);
 
     crate::early_debug::set_tx_fun(write_byte);
     crate::early_debug::set_rx_fun(read_byte);
+
+    // `earlycon` 在串口初始化之前处理，到这里才能报告
+    if let Some(v) = common::cmdline::get("earlycon").and_then(|p| p.value) {
+        println!("earlycon={v} is not supported, using stdout-path");
+    }
     Some(())
 }
 
@@ -59,7 +91,8 @@ fn read_byte() -> Result<u8, crate::early_debug::RError> {
 }
 
 pub fn setup_plat_info() -> Option<()> {
-    CPU_NUM.init(cpu_id_list().count());
+    init_logical_ids(cpu_id_list().filter(|_| !nosmp()));
+    numa::parse(&fdt()?);
     find_rams()
 }
 
@@ -67,87 +100,160 @@ fn fdt() -> Option<Fdt<'static>> {
     boot_info().fdt.and_then(|fdt| Fdt::from_ptr(fdt).ok())
 }
 
-pub fn cpu_id_list() -> impl Iterator<Item = usize> {
-    let fdt = fdt().expect("FDT not found");
-    let nodes = fdt.find_nodes("/cpus/cpu");
-    nodes
+/// FDT 所在的物理地址范围，loader 传来的是物理地址
+pub(crate) fn fdt_range() -> Option<Range<usize>> {
+    let start = boot_info().fdt?.as_ptr() as usize;
+    Some(start..start + fdt()?.total_size())
+}
+
+fn reserved_mem(node: &Node<'static>, start: usize, size: usize) -> ReservedMem {
+    ReservedMem {
+        name: node.name(),
+        start,
+        end: start + size,
+        no_map: node.find_property("no-map").is_some(),
+        reusable: node.find_property("reusable").is_some(),
+    }
+}
+
+/// `/reserved-memory` 下启用的子节点
+fn reserved_memory_children(fdt: &Fdt<'static>) -> impl Iterator<Item = Node<'static>> {
+    let parent = fdt.reserved_memory().next();
+    let parent_level = parent.as_ref().map_or(0, |n| n.level);
+    let parent_name = parent.as_ref().map(|n| n.name());
+
+    fdt.all_nodes()
+        .skip_while(move |n| Some(n.name()) != parent_name)
+        .skip(1)
+        .take_while(move |n| parent_name.is_some() && n.level > parent_level)
+        .filter(move |n| n.level == parent_level + 1)
+        .filter(|n| !matches!(n.status(), Some(Status::Disabled)))
+}
+
+/// 从 `cells` 个 u32 读一个数
+fn read_cells(values: &mut impl Iterator<Item = u32>, cells: usize) -> Option<usize> {
+    (0..cells).try_fold(0usize, |v, _| Some((v << 32) | values.next()? as usize))
+}
+
+/// 为只有 `size` 没有 `reg` 的 `/reserved-memory` 子节点分配内存，
+/// 按 `alignment` 对齐并限制在 `alloc-ranges` 内。有 `alloc-ranges` 的先分配。
+pub(crate) fn alloc_reserved_dynamic() -> Option<()> {
+    let fdt = fdt()?;
+    let parent = fdt.reserved_memory().next()?;
+    let cells = |name, default| {
+        parent
+            .find_property(name)
+            .map_or(default, |p| p.u32() as usize)
+    };
+    let addr_cells = cells("#address-cells", 2);
+    let size_cells = cells("#size-cells", 1);
+
+    let constrained = |n: &Node<'static>| n.find_property("alloc-ranges").is_some();
+    let dynamic = || reserved_memory_children(&fdt).filter(|n| n.reg().is_none());
+    let children = dynamic()
+        .filter(constrained)
+        .chain(dynamic().filter(|n| !constrained(n)));
+
+    for child in children {
+        let Some(size) = child
+            .find_property("size")
+            .and_then(|p| read_cells(&mut p.u32_list(), size_cells))
+        else {
+            continue;
+        };
+        let align = child
+            .find_property("alignment")
+            .and_then(|p| read_cells(&mut p.u32_list(), size_cells))
+            .unwrap_or(0)
+            .max(common::mem::page_size());
+
+        let mut ranges: heapless::Vec<Range<usize>, 8> = heapless::Vec::new();
+        if let Some(prop) = child.find_property("alloc-ranges") {
+            let mut values = prop.u32_list();
+            while let (Some(start), Some(size)) = (
+                read_cells(&mut values, addr_cells),
+                read_cells(&mut values, size_cells),
+            ) {
+                if ranges.push(start..start + size).is_err() {
+                    println!("{}: too many alloc-ranges, the rest ignored", child.name());
+                    break;
+                }
+            }
+        } else {
+            let _ = ranges.push(0..usize::MAX);
+        }
+
+        match common::mem::alloc_early(size, align, &ranges) {
+            Some(start) => common::mem::add_reserved_mem(reserved_mem(&child, start, size)),
+            None => {
+                println!(
+                    "{}: no memory for {size:#x} bytes of reserved memory",
+                    child.name()
+                );
+            }
+        }
+    }
+    Some(())
+}
+
+/// `/cpus` 下启用的 `cpu@*` 节点
+fn cpu_nodes(fdt: &Fdt<'static>) -> impl Iterator<Item = Node<'static>> + use<> {
+    fdt.find_nodes("/cpus/cpu")
         .filter(|node| node.name().contains("cpu@"))
         .filter(|node| !matches!(node.status(), Some(Status::Disabled)))
-        .map(|node| {
-            let reg = node
-                .reg()
-                .unwrap_or_else(|| panic!("cpu {} reg not found", node.name()))
-                .next()
-                .expect("cpu reg 0 not found");
-            reg.address as usize
-        })
+}
+
+fn cpu_reg(node: &Node<'static>) -> usize {
+    let reg = node
+        .reg()
+        .unwrap_or_else(|| panic!("cpu {} reg not found", node.name()))
+        .next()
+        .expect("cpu reg 0 not found");
+    reg.address as usize
+}
+
+pub fn cpu_id_list() -> impl Iterator<Item = usize> {
+    let fdt = fdt().expect("FDT not found");
+    cpu_nodes(&fdt).map(|node| cpu_reg(&node))
 }
 
 pub fn find_rams() -> Option<()> {
     let fdt = fdt()?;
+    let mut banks: heapless::Vec<Range<usize>, 64> = heapless::Vec::new();
     for memory in fdt.memory() {
         for region in memory.regions() {
-            let start = region.address as _;
+            let start = region.address as usize;
             if region.size == 0 {
                 continue; // Skip zero-sized regions
             }
-            let v = MemoryRegion {
-                start,
-                end: start + region.size,
-                kind: MemoryRegionKind::Ram,
-            };
-
-            common::mem::with_regions(|regions| regions.push(v).ok())?;
+            banks
+                .push(start..start + region.size)
+                .unwrap_or_else(|r| panic!("Too many memory banks, {r:#x?} dropped"));
         }
     }
+    banks.sort_unstable_by_key(|r| r.start);
+    limit::MemLimits::from_fdt(&fdt).apply(&banks, common::mem::add_region);
 
     for region in fdt.memory_reservation_block() {
-        let start = region.address as _;
-        let v = MemoryRegion {
+        let start = region.address as usize;
+        common::mem::add_reserved_mem(ReservedMem {
+            name: "memreserve",
             start,
             end: start + region.size,
-            kind: MemoryRegionKind::Reserved,
-        };
-        common::mem::with_regions(|regions| regions.push(v).ok())?;
+            no_map: false,
+            reusable: false,
+        });
     }
 
-    if let Some(reserved_node) = fdt.reserved_memory().next() {
-        if let Some(region) = reserved_node.reg().and_then(|mut r| r.next())
-            && let Some(size) = region.size
-        {
-            let start = region.address as _;
-            let v = MemoryRegion {
-                start,
-                end: start + size,
-                kind: MemoryRegionKind::Reserved,
-            };
-            common::mem::with_regions(|regions| regions.push(v).ok())?;
-        }
-
-        let parent_level = reserved_node.level;
-        let parent_name = reserved_node.name();
-
-        for child in reserved_node
-            .fdt()
-            .all_nodes()
-            .skip_while(move |n| n.name() != parent_name)
-            .skip(1)
-            .take_while(move |n| n.level > parent_level)
-            .filter(move |n| n.level == parent_level + 1)
-        {
-            if let Some(regs) = child.reg() {
-                for region in regs {
-                    if let Some(size) = region.size {
-                        let start = region.address as _;
-
-                        let v = MemoryRegion {
-                            start,
-                            end: start + size,
-                            kind: MemoryRegionKind::Reserved,
-                        };
-                        common::mem::with_regions(|regions| regions.push(v).ok())?;
-                    }
-                }
+    // 动态分配的节点在 `alloc_reserved_dynamic` 中处理
+    for child in reserved_memory_children(&fdt) {
+        let Some(regs) = child.reg() else {
+            continue;
+        };
+        for region in regs {
+            if let Some(size) = region.size {
+                let start = region.address as usize;
+                common::mem::add_reserved_mem(reserved_mem(&child, start, size));
             }
         }
     }
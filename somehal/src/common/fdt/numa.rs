@@ -0,0 +1,45 @@
+//! 解析 NUMA 拓扑，见 [`crate::numa`]。
+
+use fdt_parser::{Fdt, Node};
+
+use super::{cpu_nodes, cpu_reg};
+use crate::{common::numa, println};
+
+fn node_id(node: &Node<'static>) -> Option<usize> {
+    node.find_property("numa-node-id").map(|p| p.u32() as usize)
+}
+
+pub(super) fn parse(fdt: &Fdt<'static>) {
+    for memory in fdt.find_nodes("/memory") {
+        let node = node_id(&memory).unwrap_or(0);
+        for reg in memory.reg().into_iter().flatten() {
+            let start = reg.address as usize;
+            match reg.size {
+                Some(size) if size > 0 => numa::add_memory(start..start + size, node),
+                _ => {}
+            }
+        }
+    }
+
+    for cpu in cpu_nodes(fdt) {
+        numa::add_cpu(cpu_reg(&cpu), node_id(&cpu).unwrap_or(0));
+    }
+
+    let Some(map) = fdt
+        .all_nodes()
+        .find(|n| n.compatibles().any(|c| c == "numa-distance-map-v1"))
+        .and_then(|n| n.find_property("distance-matrix"))
+    else {
+        return;
+    };
+    let mut values = map.u32_list();
+    while let (Some(from), Some(to), Some(distance)) = (values.next(), values.next(), values.next())
+    {
+        let (from, to) = (from as usize, to as usize);
+        if from == to && distance != numa::LOCAL_DISTANCE as u32 {
+            println!("distance-map: node {from} local distance {distance} ignored");
+            continue;
+        }
+        numa::set_distance(from, to, distance.min(u8::MAX as u32) as u8);
+    }
+}
@@ -0,0 +1,82 @@
+//! 启动早期的物理内存分配，用于 CPU 栈、早期堆、区域表扩容和没有 `reg` 的保留内存。
+//!
+//! 只从区域表中的 `Ram` 分配，避开模块、保留内存（包括 `no-map`）、loader 占用的内存、
+//! 内核镜像和 FDT。优先从 `free_memory_start` 往上找，分配到它之后时把它移到块的末尾。
+//! 分配到的内存记为 `Reserved`，页帧分配器接管以后就没有 `Ram` 可分配了。
+
+use core::ops::Range;
+
+use heapless::Vec;
+use num_align::NumAlign;
+use pie_boot_if::{MemoryRegion, MemoryRegionKind, carve_usable};
+
+use super::{boot_holes, insert_region, page_size, with_regions};
+use crate::{boot_info, common::entry::boot_info_edit};
+
+/// 不限制地址范围
+pub(crate) const ANYWHERE: &[Range<usize>] = core::slice::from_ref(&(0..usize::MAX));
+
+/// 在 `regions` 的 `Ram` 中找一段落在 `ranges` 内、按 `align` 对齐的 `size` 字节，
+/// 另外避开还没插入区域表的 `pending`。
+///
+/// # Panics
+/// 找到的内存与模块重叠时 panic。
+pub(crate) fn find_free(
+    regions: &[MemoryRegion],
+    pending: Option<MemoryRegion>,
+    size: usize,
+    align: usize,
+    ranges: &[Range<usize>],
+) -> Option<usize> {
+    let mut holes: Vec<Range<usize>, 3> = boot_holes().into_iter().collect();
+    if let Some(p) = pending {
+        let _ = holes.push(p.start..p.end);
+    }
+
+    let fit = |min: usize| {
+        carve_usable(regions, &holes).find_map(|free| {
+            ranges.iter().find_map(|range| {
+                let start = free.start.max(range.start).max(min).align_up(align);
+                let end = start.checked_add(size)?;
+                (end <= free.end.min(range.end)).then_some(start)
+            })
+        })
+    };
+    let start = fit(boot_info().free_memory_start as usize).or_else(|| fit(0))?;
+
+    let end = start + size;
+    assert!(
+        !regions
+            .iter()
+            .any(|r| matches!(r.kind, MemoryRegionKind::Module) && r.start < end && start < r.end),
+        "Early allocation [{start:#x}, {end:#x}) overlaps a module"
+    );
+    Some(start)
+}
+
+/// 分配结束后更新 `free_memory_start`
+pub(crate) fn bump_free_memory_start(start: usize, end: usize) {
+    if start >= boot_info().free_memory_start as usize {
+        unsafe { boot_info_edit(|info| info.free_memory_start = end as _) };
+    }
+}
+
+/// 分配 `size` 字节物理内存并记为 `Reserved`，大小和对齐至少为一页，返回起始地址
+pub(crate) fn alloc_early(size: usize, align: usize, ranges: &[Range<usize>]) -> Option<usize> {
+    let size = size.align_up(page_size());
+    let align = align.max(page_size());
+    with_regions(|regions| {
+        let start = find_free(regions, None, size, align, ranges)?;
+        let end = start + size;
+        bump_free_memory_start(start, end);
+        insert_region(
+            regions,
+            MemoryRegion {
+                start,
+                end,
+                kind: MemoryRegionKind::Reserved,
+            },
+        );
+        Some(start)
+    })
+}
@@ -0,0 +1,330 @@
+//! 物理页帧分配器，伙伴算法。
+//!
+//! 管理 [`MemoryRegionKind::Usable`] 区域，按 NUMA 节点切开，再在 4G 处切开分成
+//! [`Zone::Dma32`] 和 [`Zone::Normal`]。每段可用内存开头放一个字节数组记录每页
+//! 的空闲块阶数，空闲链表的节点放在空闲页本身，通过线性映射访问。
+
+use core::ops::Range;
+
+use heapless::Vec;
+use kdef_pgtable::{PAGE_SHIFT, PAGE_SIZE};
+use log::{debug, warn};
+use num_align::NumAlign;
+use spin::Mutex;
+
+use super::MAX_RESERVED_MEM;
+use super::{MemoryRegion, MemoryRegionKind, kliner_offset, with_regions};
+use crate::common::numa::{self, MAX_MEM_BLKS};
+
+/// 最大阶，一次最多分配 `2^MAX_ORDER` 页
+pub const MAX_ORDER: usize = 10;
+
+const DMA32_LIMIT: usize = 1 << 32;
+/// `meta` 中表示该页不是空闲块起始页
+const NOT_FREE: u8 = u8::MAX;
+const NONE: usize = usize::MAX;
+/// 最多的连续可用内存段数，每段保留内存、`memmap=` 空洞和 NUMA 节点边界都可能多切出一段，
+/// 另外留一些给 4G 处的切分
+const MAX_AREAS: usize = MAX_RESERVED_MEM + MAX_MEM_BLKS + 8;
+
+/// 内存分区
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    /// 物理地址低于 4G，给只能 32 位寻址的设备使用
+    Dma32,
+    Normal,
+}
+
+impl Zone {
+    fn of(paddr: usize) -> Self {
+        if paddr < DMA32_LIMIT {
+            Zone::Dma32
+        } else {
+            Zone::Normal
+        }
+    }
+}
+
+/// 页帧统计，单位为页
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStats {
+    pub total: usize,
+    pub free: usize,
+}
+
+/// 空闲链表节点，放在空闲块的第一页
+struct FreeNode {
+    prev: usize,
+    next: usize,
+}
+
+/// 一段连续的可用内存
+struct Area {
+    zone: Zone,
+    /// NUMA 节点
+    node: usize,
+    /// 第一页的页号
+    start_pfn: usize,
+    frames: usize,
+    /// 每页一个字节，空闲块起始页记录阶数，其它为 [`NOT_FREE`]
+    meta: *mut u8,
+    /// 每阶空闲链表头的页号
+    free_lists: [usize; MAX_ORDER + 1],
+    free: usize,
+}
+
+unsafe impl Send for Area {}
+
+impl Area {
+    fn contains(&self, pfn: usize) -> bool {
+        (self.start_pfn..self.start_pfn + self.frames).contains(&pfn)
+    }
+
+    fn node(pfn: usize) -> *mut FreeNode {
+        ((pfn << PAGE_SHIFT) + kliner_offset()) as *mut FreeNode
+    }
+
+    fn meta(&self, pfn: usize) -> u8 {
+        unsafe { self.meta.add(pfn - self.start_pfn).read() }
+    }
+
+    fn set_meta(&mut self, pfn: usize, order: u8) {
+        unsafe { self.meta.add(pfn - self.start_pfn).write(order) }
+    }
+
+    /// `[pfn, pfn + 2^order)` 是否有页已经空闲：落在更大的空闲块中，或范围内有空闲块
+    fn overlaps_free(&self, pfn: usize, order: usize) -> bool {
+        let in_block = (0..=MAX_ORDER).any(|o| {
+            let head = pfn & !((1 << o) - 1);
+            self.contains(head) && self.meta(head) == o as u8
+        });
+        let end = (pfn + (1 << order)).min(self.start_pfn + self.frames);
+        in_block || (pfn..end).any(|p| self.meta(p) != NOT_FREE)
+    }
+
+    fn push(&mut self, pfn: usize, order: usize) {
+        let head = self.free_lists[order];
+        unsafe {
+            Self::node(pfn).write(FreeNode {
+                prev: NONE,
+                next: head,
+            });
+            if head != NONE {
+                (*Self::node(head)).prev = pfn;
+            }
+        }
+        self.free_lists[order] = pfn;
+        self.set_meta(pfn, order as u8);
+    }
+
+    fn remove(&mut self, pfn: usize, order: usize) {
+        let FreeNode { prev, next } = unsafe { Self::node(pfn).read() };
+        if prev == NONE {
+            self.free_lists[order] = next;
+        } else {
+            unsafe { (*Self::node(prev)).next = next };
+        }
+        if next != NONE {
+            unsafe { (*Self::node(next)).prev = prev };
+        }
+        self.set_meta(pfn, NOT_FREE);
+    }
+
+    fn alloc(&mut self, order: usize) -> Option<usize> {
+        let found = (order..=MAX_ORDER).find(|&o| self.free_lists[o] != NONE)?;
+        let pfn = self.free_lists[found];
+        self.remove(pfn, found);
+        // 拆分，后一半放回低一阶的链表
+        for o in (order..found).rev() {
+            self.push(pfn + (1 << o), o);
+        }
+        self.free -= 1 << order;
+        Some(pfn)
+    }
+
+    fn free(&mut self, mut pfn: usize, mut order: usize) {
+        self.free += 1 << order;
+        while order < MAX_ORDER {
+            let buddy = pfn ^ (1 << order);
+            if !self.contains(buddy) || self.meta(buddy) != order as u8 {
+                break;
+            }
+            self.remove(buddy, order);
+            pfn = pfn.min(buddy);
+            order += 1;
+        }
+        self.push(pfn, order);
+    }
+
+    /// 把 `[start, end)` 按对齐拆成尽可能大的块放入空闲链表
+    fn add_range(&mut self, mut start: usize, end: usize) {
+        while start < end {
+            let mut order = (start.trailing_zeros() as usize).min(MAX_ORDER);
+            while start + (1 << order) > end {
+                order -= 1;
+            }
+            self.free(start, order);
+            start += 1 << order;
+        }
+    }
+}
+
+struct FrameAllocator {
+    areas: Vec<Area, MAX_AREAS>,
+}
+
+static FRAMES: Mutex<FrameAllocator> = Mutex::new(FrameAllocator { areas: Vec::new() });
+
+fn add_area(areas: &mut Vec<Area, MAX_AREAS>, range: Range<usize>, node: usize) {
+    let start = range.start.align_up(PAGE_SIZE);
+    let end = range.end.align_down(PAGE_SIZE);
+    if start >= end {
+        return;
+    }
+    let frames = (end - start) / PAGE_SIZE;
+    let meta_frames = frames.div_ceil(PAGE_SIZE);
+    if meta_frames >= frames {
+        warn!("frames: [{start:#x}, {end:#x}) too small for its metadata, ignored");
+        return;
+    }
+    if areas.is_full() {
+        warn!("frames: too many memory areas, [{start:#x}, {end:#x}) ignored");
+        return;
+    }
+
+    let mut area = Area {
+        zone: Zone::of(start),
+        node,
+        start_pfn: start >> PAGE_SHIFT,
+        frames,
+        meta: (start + kliner_offset()) as *mut u8,
+        free_lists: [NONE; MAX_ORDER + 1],
+        free: 0,
+    };
+    unsafe { area.meta.write_bytes(NOT_FREE, frames) };
+    area.add_range(area.start_pfn + meta_frames, area.start_pfn + frames);
+
+    debug!(
+        "frames {:?} node {node}: [{start:#x}, {end:#x}) free {:#x}",
+        area.zone,
+        area.free * PAGE_SIZE
+    );
+    areas
+        .push(area)
+        .unwrap_or_else(|_| unreachable!("checked above"));
+}
+
+pub(crate) fn init_frames() {
+    let mut frames = FRAMES.lock();
+    with_regions(|regions| {
+        for r in regions
+            .iter()
+            .filter(|r| r.kind == MemoryRegionKind::Usable)
+        {
+            add_usable(&mut frames.areas, r);
+        }
+    });
+}
+
+/// 按节点切开，跨过 4G 的再切开，分别加入
+fn add_usable(areas: &mut Vec<Area, MAX_AREAS>, region: &MemoryRegion) {
+    for (range, node) in numa::region_nodes(region) {
+        if range.start < DMA32_LIMIT && range.end > DMA32_LIMIT {
+            add_area(areas, range.start..DMA32_LIMIT, node);
+            add_area(areas, DMA32_LIMIT..range.end, node);
+        } else {
+            add_area(areas, range, node);
+        }
+    }
+}
+
+/// 页帧分配器初始化以后再交给它一段内存，需要在线性映射中
+pub(crate) fn add_frames(range: Range<usize>) {
+    let region = MemoryRegion {
+        start: range.start,
+        end: range.end,
+        kind: MemoryRegionKind::Usable,
+    };
+    add_usable(&mut FRAMES.lock().areas, &region);
+}
+
+/// 分配 `2^order` 个连续的物理页，返回物理地址，优先使用 [`Zone::Normal`]。
+pub fn alloc_frames(order: usize) -> Option<usize> {
+    alloc_frames_in(Zone::Normal, order).or_else(|| alloc_frames_in(Zone::Dma32, order))
+}
+
+/// 从 `zone` 分配 `2^order` 个连续的物理页，返回物理地址。
+pub fn alloc_frames_in(zone: Zone, order: usize) -> Option<usize> {
+    if order > MAX_ORDER {
+        return None;
+    }
+    let mut frames = FRAMES.lock();
+    frames
+        .areas
+        .iter_mut()
+        .filter(|a| a.zone == zone)
+        .find_map(|a| a.alloc(order))
+        .map(|pfn| pfn << PAGE_SHIFT)
+}
+
+/// 从 NUMA 节点 `node` 分配 `2^order` 个连续的物理页，返回物理地址，优先使用 [`Zone::Normal`]。
+///
+/// 不会退回到其它节点。
+pub fn alloc_frames_on(node: usize, order: usize) -> Option<usize> {
+    if order > MAX_ORDER {
+        return None;
+    }
+    let mut frames = FRAMES.lock();
+    [Zone::Normal, Zone::Dma32]
+        .into_iter()
+        .find_map(|zone| {
+            frames
+                .areas
+                .iter_mut()
+                .filter(|a| a.zone == zone && a.node == node)
+                .find_map(|a| a.alloc(order))
+        })
+        .map(|pfn| pfn << PAGE_SHIFT)
+}
+
+/// 释放 [`alloc_frames`] 分配的页，`order` 须与分配时相同。
+///
+/// # Panics
+/// `paddr` 不属于分配器管理的内存，或其中有页已经空闲时 panic。
+pub fn free_frames(paddr: usize, order: usize) {
+    let pfn = paddr >> PAGE_SHIFT;
+    let mut frames = FRAMES.lock();
+    let area = frames
+        .areas
+        .iter_mut()
+        .find(|a| a.contains(pfn))
+        .unwrap_or_else(|| panic!("free_frames: {paddr:#x} is not managed"));
+    assert!(
+        !area.overlaps_free(pfn, order),
+        "free_frames: {paddr:#x} order {order} double free"
+    );
+    area.free(pfn, order);
+}
+
+/// 页帧统计，`zone` 为 `None` 时统计所有分区
+pub fn frame_stats(zone: Option<Zone>) -> FrameStats {
+    let frames = FRAMES.lock();
+    frames
+        .areas
+        .iter()
+        .filter(|a| zone.is_none_or(|z| a.zone == z))
+        .fold(FrameStats::default(), |s, a| FrameStats {
+            total: s.total + a.frames,
+            free: s.free + a.free,
+        })
+}
+
+/// 能容纳 `size` 字节的最小阶
+pub const fn order_of(size: usize) -> usize {
+    let pages = size.div_ceil(PAGE_SIZE);
+    if pages <= 1 {
+        0
+    } else {
+        (usize::BITS - (pages - 1).leading_zeros()) as usize
+    }
+}
@@ -0,0 +1,75 @@
+//! 早期堆。
+//!
+//! `virt_entry` 用 [`alloc_early`] 预留 [`EARLY_HEAP_SIZE`] 作为早期堆。
+//! 启用 `heap` feature 时由内置的全局分配器使用，不够时从页帧分配器扩充；
+//! 否则通过 [`take_early_heap`] 交给用户自己的全局分配器。
+
+#[cfg(not(feature = "heap"))]
+use core::ops::Range;
+
+use kdef_pgtable::SZ_2M;
+
+use super::{ANYWHERE, alloc_early, page_size, phys_to_virt};
+
+/// 早期堆大小。
+///
+/// 启用 `heap` feature 时堆从页帧分配器扩充，每次一个页块，单次分配最大为
+/// `page_size() << MAX_ORDER`，更大的分配直接失败。
+pub const EARLY_HEAP_SIZE: usize = SZ_2M;
+
+#[cfg(not(feature = "heap"))]
+#[unsafe(link_section = ".data")]
+static mut EARLY_HEAP: Range<usize> = 0..0;
+
+/// 预留早期堆
+pub(crate) fn init_early_heap() {
+    let start =
+        alloc_early(EARLY_HEAP_SIZE, page_size(), ANYWHERE).expect("No memory for early heap");
+    let vstart = phys_to_virt(start) as usize;
+    #[cfg(feature = "heap")]
+    unsafe {
+        builtin::HEAP.lock().init(vstart, EARLY_HEAP_SIZE)
+    };
+    #[cfg(not(feature = "heap"))]
+    unsafe {
+        EARLY_HEAP = vstart..vstart + EARLY_HEAP_SIZE
+    };
+}
+
+/// 取走早期堆的虚拟地址范围，只能取一次。
+///
+/// 用于自带全局分配器，之后需要更多内存时从 [`alloc_frames`](super::alloc_frames) 分配。
+#[cfg(not(feature = "heap"))]
+pub fn take_early_heap() -> Option<Range<usize>> {
+    let heap = unsafe { (&raw mut EARLY_HEAP).replace(0..0) };
+    (!heap.is_empty()).then_some(heap)
+}
+
+#[cfg(feature = "heap")]
+mod builtin {
+    use core::alloc::Layout;
+
+    use buddy_system_allocator::{Heap, LockedHeapWithRescue};
+    use kdef_pgtable::SZ_2M;
+
+    use crate::mem::{MAX_ORDER, alloc_frames, order_of, phys_to_virt};
+
+    #[global_allocator]
+    pub(super) static HEAP: LockedHeapWithRescue<32> = LockedHeapWithRescue::new(rescue);
+
+    /// 堆不够时从页帧分配器扩充，每次至少 2M。
+    ///
+    /// 页块之间不连续，超过最大阶的分配扩充了也放不下，不扩充。
+    fn rescue(heap: &mut Heap<32>, layout: &Layout) {
+        let size = layout.size().max(layout.align());
+        if order_of(size) > MAX_ORDER {
+            return;
+        }
+        let order = order_of(size.max(SZ_2M)).min(MAX_ORDER);
+        if let Some(paddr) = alloc_frames(order) {
+            let start = phys_to_virt(paddr) as usize;
+            let end = start + (crate::mem::page_size() << order);
+            unsafe { heap.add_to_heap(start, end) };
+        }
+    }
+}
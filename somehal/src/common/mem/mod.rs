@@ -1,37 +1,90 @@
 use core::ops::Range;
 
 use heapless::Vec;
-use kdef_pgtable::{KLINER_OFFSET, PAGE_SIZE};
+use kdef_pgtable::PAGE_SIZE;
 use num_align::{NumAlign, NumAssertAlign};
-use pie_boot_if::{MemoryRegion, MemoryRegionKind};
+use pie_boot_if::{MemoryRegion, MemoryRegionKind, RegionMap, VaLayout};
 use spin::Mutex;
 
 pub use page_table_generic::PagingError;
 
-use crate::{boot_info, common::entry::boot_info_edit};
+use crate::{
+    boot_info,
+    common::{entry::boot_info_edit, numa::MAX_NUMA_NODES, percpu},
+};
 
+mod early;
+mod frame;
+mod heap;
+mod regions;
+mod reserved;
 mod stack;
 
-pub(crate) use stack::init_percpu_stack;
+pub(crate) use early::{ANYWHERE, alloc_early};
+pub(crate) use frame::init_frames;
+pub use frame::{
+    FrameStats, MAX_ORDER, Zone, alloc_frames, alloc_frames_in, alloc_frames_on, frame_stats,
+    free_frames, order_of,
+};
+pub use heap::EARLY_HEAP_SIZE;
+pub(crate) use heap::init_early_heap;
+#[cfg(not(feature = "heap"))]
+pub use heap::take_early_heap;
+pub(crate) use regions::RegionStore;
+pub(crate) use reserved::add_reserved_mem;
+pub use reserved::{
+    MAX_RESERVED_MEM, ReservedMem, find_reserved_mem, reclaim_reserved_mem, reserved_mem_list,
+};
 pub use stack::{cpu_id_list, cpu_stack};
+pub(crate) use stack::{guard_page_owner, init_percpu_stack, percpu_area};
 
-type MemoryRegionVec = Vec<MemoryRegion, 128>;
+const MAX_RAM_RANGES: usize = 128;
+type MemoryRegionVec = Vec<MemoryRegion, MAX_RAM_RANGES>;
+/// 按地址排序、互不重叠的内存区域，重叠时按 [`MemoryRegionKind::precedence`] 覆盖
+pub(crate) type Regions = RegionMap<RegionStore>;
 
 #[unsafe(link_section = ".data")]
-static MEMORY_REGIONS: Mutex<MemoryRegionVec> = Mutex::new(Vec::new());
+static MEMORY_REGIONS: Mutex<Regions> = Mutex::new(RegionMap::new(RegionStore::new()));
 
 pub const fn page_size() -> usize {
     PAGE_SIZE
 }
 
+/// 运行时虚拟地址空间布局，loader 按 CPU 支持的地址宽度确定
+pub fn va_layout() -> &'static VaLayout {
+    &boot_info().va_layout
+}
+
+/// 线性映射偏移，`vaddr = paddr + kliner_offset()`
+pub fn kliner_offset() -> usize {
+    va_layout().kliner_offset
+}
+
 pub(crate) fn with_regions<F, R>(f: F) -> R
 where
-    F: FnOnce(&mut MemoryRegionVec) -> R,
+    F: FnOnce(&mut Regions) -> R,
 {
     let mut regions = MEMORY_REGIONS.lock();
     f(&mut regions)
 }
 
+/// 添加一个区域，区域表满且无法扩容时 panic
+pub(crate) fn add_region(region: MemoryRegion) {
+    with_regions(|regions| insert_region(regions, region));
+}
+
+fn insert_region(regions: &mut Regions, region: MemoryRegion) {
+    let mut next = Some(region);
+    while let Some(r) = next {
+        // 插入过程中扩容时，`r` 还没完全进入区域表
+        regions.storage_mut().set_pending(Some(r));
+        regions.insert(r).unwrap_or_else(|e| panic!("{e}: {r:?}"));
+        regions.storage_mut().set_pending(None);
+        // 扩容占用的空间也要记录下来
+        next = regions.storage_mut().take_grown();
+    }
+}
+
 pub(crate) fn clean_bss() {
     unsafe extern "C" {
         fn __bss_start();
@@ -46,68 +99,37 @@ pub(crate) fn clean_bss() {
     }
 }
 
-/// 对内存区域按类型进行合并和去重
-/// 相同类型的重叠或相邻区域会被合并
-pub fn merge_and_dedup_regions() {
-    let mut regions = MEMORY_REGIONS.lock();
-
-    // 动态收集所有存在的类型
-    let mut kinds: Vec<MemoryRegionKind, 16> = Vec::new();
-    for region in regions.iter() {
-        if !kinds.contains(&region.kind) {
-            let _ = kinds.push(region.kind);
-        }
+/// 不在区域表里、但不能使用的内存：内核镜像和 FDT
+fn boot_holes() -> Vec<Range<usize>, 2> {
+    let mut holes = Vec::new();
+    let _ = holes.push(kimage_range_phys());
+    if let Some(fdt) = crate::common::fdt::fdt_range() {
+        let _ = holes.push(fdt);
     }
+    holes
+}
 
-    for kind in kinds {
-        // 收集该类型的所有区域
-        let mut type_regions: MemoryRegionVec = MemoryRegionVec::new();
-        for region in regions.iter() {
-            if region.kind == kind {
-                let _ = type_regions.push(*region);
-            }
-        }
-
-        if type_regions.len() < 2 {
-            continue;
-        }
-
-        // 按起始地址排序，便于合并
-        type_regions.sort_by_key(|r| r.start);
-
-        // 合并重叠或相邻的区域
-        let mut merged: MemoryRegionVec = MemoryRegionVec::new();
-        for region in type_regions {
-            if let Some(last) = merged.last_mut() {
-                // 检查是否重叠或相邻（end >= start 表示重叠或相邻）
-                if last.end >= region.start {
-                    // 扩展区域范围
-                    last.end = last.end.max(region.end);
-                } else {
-                    let _ = merged.push(region);
-                }
-            } else {
-                let _ = merged.push(region);
-            }
-        }
-
-        // 从原列表中移除该类型的所有区域
-        regions.retain(|r| r.kind != kind);
-
-        // 将合并后的区域添加回去
-        for region in merged {
-            let _ = regions.push(region);
+/// 从 RAM 中去掉其它所有区域、内核镜像和 FDT，生成 [`MemoryRegionKind::Usable`] 区域
+pub(crate) fn init_usable() {
+    let holes = boot_holes();
+    with_regions(|regions| {
+        // 插入会改变区域表，每次只取第一段
+        loop {
+            let Some(usable) = pie_boot_if::carve_usable(regions, &holes).next() else {
+                break;
+            };
+            insert_region(regions, usable);
         }
-    }
+        // 之后的内存交给页帧分配器
+        regions.storage_mut().seal();
+    });
 }
 
 pub(crate) fn init_regions(args_regions: &[MemoryRegion]) {
     let mut regions = MEMORY_REGIONS.lock();
-    regions
-        .extend_from_slice(args_regions)
-        .expect("Memory regions overflow");
 
-    for region in regions.iter_mut() {
+    for &region in args_regions {
+        let mut region = region;
         if !region.end.is_aligned_to(page_size()) {
             let is_main = region.end == boot_info().free_memory_start as usize;
 
@@ -117,91 +139,70 @@ pub(crate) fn init_regions(args_regions: &[MemoryRegion]) {
                 unsafe { boot_info_edit(|info| info.free_memory_start = region.end as _) };
             }
         }
+        insert_region(&mut regions, region);
+    }
+
+    // loader 的 Bootloader 区域可能跨过模块，模块优先级更高
+    for module in boot_info().modules.iter() {
+        insert_region(
+            &mut regions,
+            MemoryRegion {
+                start: module.start,
+                end: module.end,
+                kind: MemoryRegionKind::Module,
+            },
+        );
     }
 
     mainmem_start_rsv(&mut regions);
 }
 
-fn find_main(regions: &MemoryRegionVec) -> Option<MemoryRegion> {
+/// 预留内核所在内存开头到内核之间的空间
+fn mainmem_start_rsv(regions: &mut Regions) -> Option<()> {
     let lma = boot_info().kimage_start_lma as usize;
-    regions
-        .iter()
-        .find(|r| {
-            let is_ram = matches!(r.kind, MemoryRegionKind::Ram);
-            let in_range = r.start <= lma && r.end > lma;
-            is_ram && in_range
-        })
-        .copied()
-}
-
-fn mainmem_start_rsv(regions: &mut MemoryRegionVec) -> Option<()> {
-    let mainmem = find_main(regions)?;
+    let main = regions
+        .find(lma)
+        .filter(|r| matches!(r.kind, MemoryRegionKind::Ram))?;
+
+    // 内核所在内存的起始地址，向前跨过相连的区域
+    let mut i = regions.partition_point(|r| r.end <= main.start);
+    while i > 0 && regions[i - 1].end == regions[i].start {
+        i -= 1;
+    }
+    let start = regions[i].start;
 
-    let mut start = mainmem.start;
     unsafe extern "C" {
         fn _text();
     }
-    let mut end = _text as usize - boot_info().kcode_offset();
-
-    // 收集需要移除的 reserved 区域的索引
-    let mut indices_to_remove: heapless::Vec<usize, 16> = heapless::Vec::new();
-
-    // 遍历现有的 reserved 区域，调整新区域的范围以排除重叠部分
-    for (i, r) in regions.iter().enumerate() {
-        if !matches!(r.kind, MemoryRegionKind::Reserved) {
-            continue;
-        }
-
-        // 检查是否有重叠
-        if !(end <= r.start || start >= r.end) {
-            // 如果现有 reserved 区域完全包含了新区域，则无需添加
-            if r.start <= start && r.end >= end {
-                return Some(());
-            }
-
-            // 如果现有 reserved 区域完全在新区域中间，标记移除
-            if r.start >= start && r.end <= end {
-                let _ = indices_to_remove.push(i);
-                continue;
-            }
-
-            // 如果现有 reserved 区域与新区域的开始部分重叠
-            if r.start <= start && r.end > start && r.end < end {
-                start = r.end;
-            }
-
-            // 如果现有 reserved 区域与新区域的结束部分重叠
-            if r.start > start && r.start < end && r.end >= end {
-                end = r.start;
-            }
-        }
-    }
-
-    // 从后往前移除标记的区域（避免索引变化问题）
-    for &i in indices_to_remove.iter().rev() {
-        regions.swap_remove(i);
-    }
-
-    // 检查调整后的区域是否仍然有效
-    if start >= end {
-        return Some(());
-    }
-
-    // 添加新的 reserved 区域
-    let _ = regions.push(MemoryRegion {
-        kind: MemoryRegionKind::Reserved,
-        start,
-        end,
-    });
+    let end = _text as usize - boot_info().kcode_offset();
+
+    // 与已有 Reserved 区域的重叠由区域表合并
+    insert_region(
+        regions,
+        MemoryRegion {
+            kind: MemoryRegionKind::Reserved,
+            start,
+            end,
+        },
+    );
 
     Some(())
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CacheKind {
+    /// Device-nGnRE
     Device,
+    /// 回写
     Normal,
+    /// 不可缓存的 Normal 内存
     NoCache,
+    /// 写合并（Normal-NC），用于帧缓冲、PCIe BAR
+    WriteCombining,
+    /// 写透
+    WriteThrough,
+    /// Device-nGnRnE，严格按序，不提前应答写
+    DeviceStrict,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -230,92 +231,62 @@ pub struct MapRangeConfig {
     pub cache: CacheKind,
     pub access: AccessKind,
     pub cpu_share: bool,
+    /// 可以使用的最大块大小，`None` 表示不限制
+    pub max_page_size: Option<usize>,
+    /// 可以使用的最小页大小，对齐不满足时映射失败，不退回更小的页
+    pub min_page_size: Option<usize>,
 }
 
-fn region_ram_and_rsv() -> alloc::vec::Vec<MemoryRegion> {
-    let src = MEMORY_REGIONS.lock().to_vec();
-    let mut out: alloc::vec::Vec<MemoryRegion> = alloc::vec::Vec::new();
-
-    for region in src {
-        // 只处理 RAM 和 Reserved 类型的区域
-        if !matches!(
-            region.kind,
-            MemoryRegionKind::Ram | MemoryRegionKind::Reserved
-        ) {
+/// 需要线性映射的内存：RAM 以及其中的各类区域，相连的合并成一段，去掉 `no-map` 的保留内存、内核镜像和
+/// CPU 栈块（由 [`percpu_stacks_to_map`] 逐个映射栈，守护页不映射）
+fn region_ram_and_rsv() -> MemoryRegionVec {
+    let regions = MEMORY_REGIONS.lock();
+    let mut out = MemoryRegionVec::new();
+
+    for &region in regions.iter().filter(|r| {
+        matches!(
+            r.kind,
+            MemoryRegionKind::Ram
+                | MemoryRegionKind::Usable
+                | MemoryRegionKind::Reserved
+                | MemoryRegionKind::Bootloader
+                | MemoryRegionKind::Module
+        )
+    }) {
+        // 区域表已排序，只需和上一段比较
+        if let Some(last) = out.last_mut()
+            && last.end == region.start
+        {
+            last.end = region.end;
             continue;
         }
-
-        let mut merged = false;
-
-        // 尝试与现有区域合并
-        for o in &mut out {
-            // 检查是否有重叠或相邻
-            if (o.start..o.end).contains(&region.start) ||
-                (o.start..o.end).contains(&(region.end.saturating_sub(1))) ||
-                (region.start..region.end).contains(&o.start) ||
-                (region.start..region.end).contains(&(o.end.saturating_sub(1))) ||
-                // 相邻区域
-                o.end == region.start ||
-                region.end == o.start
-            {
-                // 合并区域：扩展边界
-                o.start = o.start.min(region.start);
-                o.end = o.end.max(region.end);
-                merged = true;
-                break;
-            }
-        }
-
-        // 如果没有合并，添加新区域
-        if !merged {
-            out.push(region);
-        }
-    }
-
-    // 多轮合并，直到没有更多可合并的区域
-    loop {
-        let mut changed = false;
-        let mut i = 0;
-
-        while i < out.len() {
-            let mut j = i + 1;
-            while j < out.len() {
-                if out[i].kind == out[j].kind
-                    && (
-                        // 检查重叠或相邻
-                        (out[i].start..out[i].end).contains(&out[j].start)
-                            || (out[i].start..out[i].end).contains(&(out[j].end.saturating_sub(1)))
-                            || (out[j].start..out[j].end).contains(&out[i].start)
-                            || (out[j].start..out[j].end).contains(&(out[i].end.saturating_sub(1)))
-                            || out[i].end == out[j].start
-                            || out[j].end == out[i].start
-                    )
-                {
-                    // 合并区域
-                    out[i].start = out[i].start.min(out[j].start);
-                    out[i].end = out[i].end.max(out[j].end);
-                    out.swap_remove(j);
-                    changed = true;
-                } else {
-                    j += 1;
-                }
-            }
-            i += 1;
-        }
-
-        if !changed {
-            break;
-        }
+        out.push(MemoryRegion {
+            kind: MemoryRegionKind::Ram,
+            ..region
+        })
+        .expect("Too many RAM ranges to map");
     }
 
-    out
+    // 内核镜像只通过 kcode 地址访问，不留可写的线性映射别名
+    let mut holes: Vec<Range<usize>, { MAX_RESERVED_MEM + 1 + MAX_NUMA_NODES }> =
+        reserved::no_map_ranges().into_iter().collect();
+    let _ = holes.push(kimage_range_phys());
+    holes.extend(stack::percpu_stack_blocks().iter().cloned());
+    pie_boot_if::carve_usable(&out, &holes).collect()
 }
 
-pub(crate) fn regions_to_map() -> alloc::vec::Vec<MapRangeConfig> {
-    let mut map_ranges = alloc::vec::Vec::new();
+/// 不依赖堆，`init_mmu` 可以在全局分配器可用之前调用
+pub(crate) fn regions_to_map() -> Vec<MapRangeConfig, { MAX_RAM_RANGES + 6 }> {
+    let mut map_ranges = Vec::new();
+    let mut push = |config: MapRangeConfig| {
+        let name = config.name;
+        map_ranges
+            .push(config)
+            .unwrap_or_else(|_| panic!("Too many ranges to map, `{name}` dropped"));
+    };
 
     for region in region_ram_and_rsv() {
-        map_ranges.push(MapRangeConfig {
+        push(MapRangeConfig {
             vaddr: phys_to_virt(region.start),
             paddr: region.start,
             size: region.end - region.start,
@@ -323,77 +294,112 @@ pub(crate) fn regions_to_map() -> alloc::vec::Vec<MapRangeConfig> {
             cache: CacheKind::Normal,
             access: AccessKind::ReadWrite,
             cpu_share: true,
+            max_page_size: None,
+            min_page_size: None,
         });
     }
 
     if let Some(d) = &boot_info().debug_console {
         let start = d.base_phys.align_down(PAGE_SIZE);
-        map_ranges.push(MapRangeConfig {
-            vaddr: (start + KLINER_OFFSET) as *mut u8,
+        push(MapRangeConfig {
+            vaddr: (start + kliner_offset()) as *mut u8,
             paddr: start,
             size: PAGE_SIZE,
             name: "debug-con",
             cache: CacheKind::Device,
             access: AccessKind::ReadWrite,
             cpu_share: true,
+            max_page_size: None,
+            min_page_size: None,
         });
     }
 
-    map_ranges.push(ld_range_to_map_config(
+    // W^X：只有 text 可执行，可写的都不可执行
+    push(ld_range_to_map_config(
         "text",
         ld::text,
         true,
         AccessKind::ReadExecute,
     ));
-    map_ranges.push(ld_range_to_map_config(
+    push(ld_range_to_map_config(
         "rodata",
         ld::rodata,
         true,
-        AccessKind::ReadExecute,
+        AccessKind::Read,
     ));
-    map_ranges.push(ld_range_to_map_config(
+    push(ld_range_to_map_config(
         "data",
         ld::data,
         true,
-        AccessKind::ReadWriteExecute,
+        AccessKind::ReadWrite,
     ));
-    map_ranges.push(ld_range_to_map_config(
+    push(ld_range_to_map_config(
         "bss",
         ld::bss,
         true,
-        AccessKind::ReadWriteExecute,
+        AccessKind::ReadWrite,
     ));
-    map_ranges.push(ld_range_to_map_config(
+    push(ld_range_to_map_config(
         "stack0",
         ld::stack0,
         false,
-        AccessKind::ReadWriteExecute,
+        AccessKind::ReadWrite,
     ));
 
-    let percpu_stack = stack::percpu_stack_range();
-    if !percpu_stack.is_empty() {
-        map_ranges.push(MapRangeConfig {
-            vaddr: (percpu_stack.start + boot_info().kcode_offset()) as *mut u8,
-            paddr: percpu_stack.start,
-            size: percpu_stack.count(),
-            name: "percpu-stack",
-            cache: CacheKind::Normal,
-            access: AccessKind::ReadWriteExecute,
-            cpu_share: false,
-        });
-    }
-
     map_ranges
 }
+/// 启动 CPU 以外各 CPU 的栈和其上方的每 CPU 区域，在线性映射中，两个 CPU 之间隔着不映射的守护页
+pub(crate) fn percpu_stacks_to_map() -> impl Iterator<Item = MapRangeConfig> {
+    stack::secondary_stacks().map(|(_, stack)| MapRangeConfig {
+        vaddr: phys_to_virt(stack.start),
+        paddr: stack.start,
+        size: stack.len() + percpu::area_size(),
+        name: "percpu-stack",
+        cache: CacheKind::Normal,
+        access: AccessKind::ReadWrite,
+        cpu_share: true,
+        max_page_size: None,
+        min_page_size: None,
+    })
+}
+
 pub fn phys_to_virt(p: usize) -> *mut u8 {
     let v = if kimage_range_phys().contains(&p) {
         p + boot_info().kcode_offset()
     } else {
         // MMIO or other reserved regions
-        p + KLINER_OFFSET
+        p + kliner_offset()
     };
     v as *mut u8
 }
+/// 内核镜像和线性映射中 RAM 的地址直接换算，其它地址返回 `None`，需要查页表
+pub(crate) fn virt_to_phys_fast(vaddr: usize) -> Option<usize> {
+    let paddr = vaddr.wrapping_sub(boot_info().kcode_offset());
+    if kimage_range_phys().contains(&paddr) {
+        return Some(paddr);
+    }
+    if !va_layout().vm_layout().linear.contains(vaddr) {
+        return None;
+    }
+    let paddr = vaddr - kliner_offset();
+    (is_ram(paddr) && !kimage_range_phys().contains(&paddr)).then_some(paddr)
+}
+
+/// `paddr` 是否是 RAM（包括内核镜像）。`Reserved` 可能是 `no-map`，不算在内
+pub(crate) fn is_ram(paddr: usize) -> bool {
+    with_regions(|regions| {
+        regions.find(paddr).is_some_and(|r| {
+            matches!(
+                r.kind,
+                MemoryRegionKind::Ram
+                    | MemoryRegionKind::Usable
+                    | MemoryRegionKind::Bootloader
+                    | MemoryRegionKind::Module
+            )
+        })
+    })
+}
+
 fn kimage_range_phys() -> Range<usize> {
     unsafe extern "C" {
         fn __kernel_code_end();
@@ -419,6 +425,8 @@ fn ld_range_to_map_config(
         cache: CacheKind::Normal,
         access,
         cpu_share,
+        max_page_size: None,
+        min_page_size: None,
     }
 }
 
@@ -443,6 +451,7 @@ mod ld {
     ld_range!(rodata, _srodata, _erodata);
     ld_range!(data, _sdata, _edata);
     ld_range!(stack0, __cpu0_stack, __cpu0_stack_top);
+    ld_range!(stack0_guard, __cpu0_stack_guard, __cpu0_stack);
     ld_range!(bss, __bss_start, __bss_stop);
 }
 
@@ -0,0 +1,158 @@
+//! 内存区域表。
+//!
+//! 作为 [`RegionMap`](pie_boot_if::RegionMap) 的存储。先使用静态的 [`INIT_CAP`] 个槽位，
+//! 满了以后用 [`find_free`] 找一块两倍大的空间并把它记为 `Reserved`。剩下的内存交给页帧分配器后就不能
+//! 再扩容，见 [`RegionStore::seal`]。
+
+use core::{
+    fmt,
+    mem::{MaybeUninit, size_of},
+    ops::{Deref, DerefMut},
+    slice,
+};
+
+use num_align::NumAlign;
+use pie_boot_if::{MemoryRegion, MemoryRegionKind, RegionVec};
+
+use super::{
+    early::{ANYWHERE, bump_free_memory_start, find_free},
+    kliner_offset, page_size,
+};
+
+/// 静态槽位数
+pub const INIT_CAP: usize = 128;
+
+#[unsafe(link_section = ".data")]
+static mut INIT_BUF: [MaybeUninit<MemoryRegion>; INIT_CAP] = [MaybeUninit::uninit(); INIT_CAP];
+
+/// 区域表已满且无法扩容
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionOverflow {
+    /// 当前容量
+    pub cap: usize,
+}
+
+impl fmt::Display for RegionOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Memory regions overflow, capacity {}", self.cap)
+    }
+}
+
+pub struct RegionStore {
+    ptr: *mut MemoryRegion,
+    len: usize,
+    cap: usize,
+    sealed: bool,
+    /// 扩容新占用的空间，由 [`take_grown`](Self::take_grown) 取走后插入区域表
+    grown: Option<MemoryRegion>,
+    /// 正在插入的区域，扩容时要避开
+    pending: Option<MemoryRegion>,
+}
+
+unsafe impl Send for RegionStore {}
+
+impl RegionStore {
+    pub(crate) const fn new() -> Self {
+        Self {
+            ptr: core::ptr::null_mut(),
+            len: 0,
+            cap: 0,
+            sealed: false,
+            grown: None,
+            pending: None,
+        }
+    }
+
+    /// 保证还能放下 `additional` 个区域
+    pub fn reserve(&mut self, additional: usize) -> Result<(), RegionOverflow> {
+        if self.ptr.is_null() {
+            self.ptr = (&raw mut INIT_BUF).cast();
+            self.cap = INIT_CAP;
+        }
+        if self.len + additional <= self.cap {
+            return Ok(());
+        }
+        if self.sealed {
+            return Err(RegionOverflow { cap: self.cap });
+        }
+        // 多留几个槽位给新空间本身插入时的拆分
+        self.grow((self.len + additional + 4).max(self.cap * 2))
+    }
+
+    /// 设置正在插入的区域
+    pub(crate) fn set_pending(&mut self, region: Option<MemoryRegion>) {
+        self.pending = region;
+    }
+
+    /// 取走扩容新占用的空间，需要作为 `Reserved` 插入区域表
+    pub(crate) fn take_grown(&mut self) -> Option<MemoryRegion> {
+        self.grown.take()
+    }
+
+    /// 页帧分配器接管 `free_memory_start` 之后的内存，此后不能再扩容
+    pub(crate) fn seal(&mut self) {
+        self.sealed = true;
+    }
+
+    fn grow(&mut self, cap: usize) -> Result<(), RegionOverflow> {
+        let overflow = RegionOverflow { cap: self.cap };
+        let size = (cap * size_of::<MemoryRegion>()).align_up(page_size());
+        let start = find_free(self, self.pending, size, page_size(), ANYWHERE).ok_or(overflow)?;
+        let end = start + size;
+        bump_free_memory_start(start, end);
+
+        let ptr = (start + kliner_offset()) as *mut MemoryRegion;
+        unsafe { ptr.copy_from_nonoverlapping(self.ptr, self.len) };
+        self.ptr = ptr;
+        self.cap = (end - start) / size_of::<MemoryRegion>();
+        self.grown = Some(MemoryRegion {
+            start,
+            end,
+            kind: MemoryRegionKind::Reserved,
+        });
+        Ok(())
+    }
+}
+
+impl RegionVec for RegionStore {
+    type Error = RegionOverflow;
+
+    fn insert(&mut self, index: usize, region: MemoryRegion) -> Result<(), Self::Error> {
+        assert!(index <= self.len);
+        self.reserve(1)?;
+        unsafe {
+            let p = self.ptr.add(index);
+            p.copy_to(p.add(1), self.len - index);
+            p.write(region);
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    fn remove(&mut self, index: usize) -> MemoryRegion {
+        let r = self[index];
+        self.copy_within(index + 1.., index);
+        self.len -= 1;
+        r
+    }
+}
+
+impl Deref for RegionStore {
+    type Target = [MemoryRegion];
+
+    fn deref(&self) -> &Self::Target {
+        if self.ptr.is_null() {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl DerefMut for RegionStore {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        if self.ptr.is_null() {
+            return &mut [];
+        }
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
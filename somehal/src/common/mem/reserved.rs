@@ -0,0 +1,93 @@
+//! 保留内存，来自设备树的 `/reserved-memory` 子节点和 `/memreserve/`。
+//!
+//! 每段都会作为 `Reserved` 加入区域表，这里另外记录节点名和属性，供驱动查找。
+
+use core::ops::Range;
+
+use heapless::Vec;
+use pie_boot_if::{MemoryRegion, MemoryRegionKind};
+use spin::Mutex;
+
+use super::{add_region, frame::add_frames};
+use crate::println;
+
+/// 最多记录的保留内存段数
+pub const MAX_RESERVED_MEM: usize = 64;
+
+/// 一段保留内存
+#[derive(Debug, Clone, Copy)]
+pub struct ReservedMem {
+    /// 节点名，`/memreserve/` 的条目为 `"memreserve"`
+    pub name: &'static str,
+    pub start: usize,
+    pub end: usize,
+    /// `no-map`：不建立线性映射
+    pub no_map: bool,
+    /// `reusable`：所属驱动不使用时可以用 [`reclaim_reserved_mem`] 交给页帧分配器
+    pub reusable: bool,
+}
+
+static RESERVED_MEM: Mutex<Vec<ReservedMem, MAX_RESERVED_MEM>> = Mutex::new(Vec::new());
+
+/// 记录一段保留内存并加入区域表。
+///
+/// 超过 [`MAX_RESERVED_MEM`] 段时只加入区域表，驱动查不到它，`no-map` 也不再生效。
+pub(crate) fn add_reserved_mem(mem: ReservedMem) {
+    if mem.start >= mem.end {
+        return;
+    }
+    if let Err(m) = RESERVED_MEM.lock().push(mem) {
+        println!(
+            "Too many reserved memory, `{}` [{:#x}, {:#x}) only kept as Reserved",
+            m.name, m.start, m.end
+        );
+    }
+    add_region(MemoryRegion {
+        start: mem.start,
+        end: mem.end,
+        kind: MemoryRegionKind::Reserved,
+    });
+}
+
+/// `no-map` 的保留内存
+pub(crate) fn no_map_ranges() -> Vec<Range<usize>, MAX_RESERVED_MEM> {
+    RESERVED_MEM
+        .lock()
+        .iter()
+        .filter(|m| m.no_map)
+        .map(|m| m.start..m.end)
+        .collect()
+}
+
+/// 所有保留内存
+pub fn reserved_mem_list() -> Vec<ReservedMem, MAX_RESERVED_MEM> {
+    RESERVED_MEM.lock().clone()
+}
+
+fn name_matches(mem: &ReservedMem, name: &str) -> bool {
+    mem.name == name || mem.name.split('@').next() == Some(name)
+}
+
+/// 按节点名查找保留内存，`name` 可以不带 `@` 后的单元地址
+pub fn find_reserved_mem(name: &str) -> Option<ReservedMem> {
+    RESERVED_MEM
+        .lock()
+        .iter()
+        .find(|m| name_matches(m, name))
+        .copied()
+}
+
+/// 把 `reusable` 的保留内存交给页帧分配器，之后不能再找到它。
+///
+/// 没有这段保留内存、它不是 `reusable` 或是 `no-map` 时返回 `None`。区域表中仍记为 `Reserved`。
+pub fn reclaim_reserved_mem(name: &str) -> Option<ReservedMem> {
+    let mem = {
+        let mut list = RESERVED_MEM.lock();
+        let i = list
+            .iter()
+            .position(|m| name_matches(m, name) && m.reusable && !m.no_map)?;
+        list.remove(i)
+    };
+    add_frames(mem.start..mem.end);
+    Some(mem)
+}
@@ -5,85 +5,141 @@ use num_align::NumAlign;
 
 use crate::{
     boot_info,
-    common::{fdt, mem::ld::stack0},
-    mem::{page_size, phys_to_virt, with_regions},
+    common::{
+        cpu::{CPU_NUM, MAX_CPUS, mpidr_of},
+        mem::ld::{stack0, stack0_guard},
+        numa::{self, MAX_NUMA_NODES},
+        percpu,
+    },
+    mem::{ANYWHERE, alloc_early, kliner_offset, page_size, phys_to_virt},
 };
 
+/// 每块存放若干 CPU 的栈，每个栈下方留一页不映射的守护页，上方是该 CPU 的每 CPU 区域。
+/// 每个 NUMA 节点一块，放在本节点的内存中，本节点没有内存时合并到启动 CPU 所在节点的块中。
 #[unsafe(link_section = ".data")]
-static mut STACK_START: usize = 0;
+static mut STACK_BLOCKS: [Range<usize>; MAX_NUMA_NODES] = [const { 0..0 }; MAX_NUMA_NODES];
 #[unsafe(link_section = ".data")]
-static mut STACK_END: usize = 0;
+static mut STACK_BLOCK_NUM: usize = 0;
 
-pub fn cpu_id_list() -> impl Iterator<Item = usize> {
-    let mut start = unsafe { STACK_START };
-    let end = unsafe { STACK_END };
-    let len = stack0().len().align_up(page_size());
-    [boot_info().cpu_id]
-        .into_iter()
-        .chain(core::iter::from_fn(move || {
-            if start >= end {
-                return None;
-            }
-            let id = unsafe { (phys_to_virt(start) as *const usize).read() };
-            let ret = Some(id);
-            start += len;
-            ret
-        }))
+/// 以逻辑 id 为下标的栈底物理地址，启动 CPU 使用 `stack0`，0 号不用
+#[unsafe(link_section = ".data")]
+static mut CPU_STACKS: [usize; MAX_CPUS] = [0; MAX_CPUS];
+
+fn stack_size() -> usize {
+    stack0().len().align_up(page_size())
 }
 
-pub fn init_percpu_stack() -> *mut u8 {
-    unsafe {
-        let rsv_start = boot_info().free_memory_start;
-        let mut rsv_end = rsv_start.align_up(page_size());
-        STACK_START = rsv_end as _;
-        let per_size = stack0().len().align_up(page_size());
-        for cpu_id in fdt::cpu_id_list() {
-            if cpu_id == boot_info().cpu_id {
-                continue;
-            }
-
-            let start = rsv_end;
-            (start as *mut usize).write(cpu_id);
-            let end = start.add(per_size);
-            rsv_end = end;
-
-            println!("CPU {cpu_id:#x} stack: [{start:#p}, {end:#p})");
-        }
+/// 每个从核占用的大小：守护页、栈和每 CPU 区域
+fn slot_size() -> usize {
+    page_size() + stack_size() + percpu::area_size()
+}
+
+/// 各栈块的物理地址范围，包括守护页和每 CPU 区域
+pub(crate) fn percpu_stack_blocks() -> &'static [Range<usize>] {
+    unsafe { core::slice::from_raw_parts((&raw const STACK_BLOCKS).cast(), STACK_BLOCK_NUM) }
+}
+
+/// 从核的栈，不含守护页
+fn secondary_stack(cpu_id: usize) -> Range<usize> {
+    assert!(
+        (1..*CPU_NUM).contains(&cpu_id),
+        "CPU {cpu_id} out of range, {} CPUs",
+        *CPU_NUM
+    );
+    let start = unsafe { (&raw const CPU_STACKS).cast::<usize>().add(cpu_id).read() };
+    start..start + stack_size()
+}
+
+/// 启动 CPU 以外各 CPU 的逻辑 id 和栈
+pub(crate) fn secondary_stacks() -> impl Iterator<Item = (usize, Range<usize>)> {
+    (1..*CPU_NUM).map(|id| (id, secondary_stack(id)))
+}
+
+/// 从核的每 CPU 区域的虚拟地址，紧接在栈之后
+pub(crate) fn percpu_area(cpu_id: usize) -> usize {
+    phys_to_virt(secondary_stack(cpu_id).end) as usize
+}
 
-        with_regions(|ls| {
-            ls.push(crate::common::mem::MemoryRegion {
-                start: rsv_start as _,
-                end: rsv_end as _,
-                kind: crate::common::mem::MemoryRegionKind::Reserved,
-            })
-            .unwrap();
-        });
-        STACK_END = rsv_end as _;
-        rsv_end as _
+/// 虚拟地址 `vaddr` 落在哪个 CPU 栈的守护页中，返回逻辑 id。
+/// 启动 CPU 的守护页在内核镜像中，从核的在线性映射中
+pub(crate) fn guard_page_owner(vaddr: usize) -> Option<usize> {
+    if stack0_guard().contains(&vaddr) {
+        return Some(0);
     }
+    let paddr = vaddr.wrapping_sub(kliner_offset());
+    secondary_stacks()
+        .find(|(_, stack)| (stack.start - page_size()..stack.start).contains(&paddr))
+        .map(|(id, _)| id)
 }
 
-pub fn percpu_stack_range() -> Range<usize> {
-    unsafe { STACK_START..STACK_END }
+/// 所有 CPU 的逻辑 id，`0..CPU_NUM`
+pub fn cpu_id_list() -> impl Iterator<Item = usize> {
+    0..*CPU_NUM
 }
 
-pub fn cpu_stack(cpu_id: usize) -> Range<usize> {
+/// 分配一块放下 `cpus` 的守护页、栈和每 CPU 区域，只在 `ranges` 内分配
+fn alloc_block(cpus: impl Iterator<Item = usize> + Clone, ranges: &[Range<usize>]) -> bool {
+    let count = cpus.clone().count();
+    if count == 0 {
+        return true;
+    }
+    let Some(start) = alloc_early(count * slot_size(), page_size(), ranges) else {
+        return false;
+    };
+
+    let mut end = start;
+    for cpu_id in cpus {
+        end += page_size();
+        unsafe { (&raw mut CPU_STACKS).cast::<usize>().add(cpu_id).write(end) };
+        println!(
+            "CPU {cpu_id} ({:#x}) stack: [{end:#x}, {:#x}), node {}",
+            mpidr_of(cpu_id).unwrap(),
+            end + stack_size(),
+            numa::node_of_cpu(cpu_id)
+        );
+        end += stack_size();
+        unsafe { percpu::copy_template(phys_to_virt(end)) };
+        end += percpu::area_size();
+    }
     unsafe {
-        if cpu_id == boot_info().cpu_id {
-            return stack0();
-        }
+        (&raw mut STACK_BLOCKS)
+            .cast::<Range<usize>>()
+            .add(STACK_BLOCK_NUM)
+            .write(start..end);
+        STACK_BLOCK_NUM += 1;
+    }
+    true
+}
 
-        let mut start = STACK_START;
+/// 预留各 CPU 的栈。
+///
+/// 每个节点的 CPU 在本节点内存中分配，失败时和启动 CPU 所在节点的 CPU 一起分配。
+pub fn init_percpu_stack() {
+    let secondary = |node: usize| (1..*CPU_NUM).filter(move |&id| numa::node_of_cpu(id) == node);
+    let local = numa::node_of_paddr(boot_info().free_memory_start as usize).unwrap_or(0);
 
-        while start < STACK_END {
-            let id = (phys_to_virt(start) as *const usize).read();
-            let end = start + stack0().len().align_up(page_size());
-            if id == cpu_id {
-                return start..end;
-            }
-            start = end;
+    let mut fallback = [false; MAX_NUMA_NODES];
+    fallback[local] = true;
+    for node in (0..numa::node_count()).filter(|&n| n != local) {
+        if !alloc_block(secondary(node), &numa::node_memory(node)) {
+            println!("No memory on node {node} for CPU stacks, use node {local}");
+            fallback[node] = true;
         }
+    }
 
-        panic!("CPU {cpu_id:#x} stack not found");
+    let cpus = (0..numa::node_count())
+        .filter(|&n| fallback[n])
+        .flat_map(secondary);
+    assert!(alloc_block(cpus, ANYWHERE), "No memory for CPU stacks");
+}
+
+/// 逻辑 id 为 `cpu_id` 的 CPU 的栈
+///
+/// # Panics
+/// `cpu_id` 超出 CPU 数时 panic。
+pub fn cpu_stack(cpu_id: usize) -> Range<usize> {
+    if cpu_id == 0 {
+        return stack0();
     }
+    secondary_stack(cpu_id)
 }
@@ -1,4 +1,7 @@
+pub mod cmdline;
 pub mod cpu;
 pub mod entry;
 pub mod fdt;
 pub mod mem;
+pub mod numa;
+pub mod percpu;
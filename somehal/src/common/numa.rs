@@ -0,0 +1,176 @@
+//! NUMA 拓扑，来自设备树 `/memory`、`/cpus/cpu@*` 的 `numa-node-id` 和 `/distance-map`。
+//!
+//! 设备树没有 NUMA 信息时只有节点 0，所有 CPU 和 RAM 都属于它。
+
+use core::ops::Range;
+
+use heapless::Vec;
+use pie_boot_if::MemoryRegion;
+use spin::Mutex;
+
+use crate::{
+    common::cpu::{MAX_CPUS, mpidr_of},
+    println,
+};
+
+/// 最大节点数
+pub const MAX_NUMA_NODES: usize = 16;
+/// 同一节点内的距离
+pub const LOCAL_DISTANCE: u8 = 10;
+/// 没有 `distance-map` 时不同节点间的距离
+pub const REMOTE_DISTANCE: u8 = 20;
+
+/// 最多记录的 RAM bank 数
+pub const MAX_MEM_BLKS: usize = 64;
+
+struct Numa {
+    /// RAM bank 及其节点
+    mem: Vec<(Range<usize>, usize), MAX_MEM_BLKS>,
+    /// CPU id（MPIDR）及其节点
+    cpus: Vec<(usize, usize), MAX_CPUS>,
+    distance: [[u8; MAX_NUMA_NODES]; MAX_NUMA_NODES],
+    nodes: usize,
+}
+
+static NUMA: Mutex<Numa> = Mutex::new(Numa {
+    mem: Vec::new(),
+    cpus: Vec::new(),
+    distance: default_distance(),
+    nodes: 1,
+});
+
+const fn default_distance() -> [[u8; MAX_NUMA_NODES]; MAX_NUMA_NODES] {
+    let mut d = [[REMOTE_DISTANCE; MAX_NUMA_NODES]; MAX_NUMA_NODES];
+    let mut i = 0;
+    while i < MAX_NUMA_NODES {
+        d[i][i] = LOCAL_DISTANCE;
+        i += 1;
+    }
+    d
+}
+
+fn check_node(node: usize) -> usize {
+    assert!(
+        node < MAX_NUMA_NODES,
+        "NUMA node {node} out of range, max {MAX_NUMA_NODES}"
+    );
+    node
+}
+
+/// 设备树中的节点号，超出 [`MAX_NUMA_NODES`] 时算节点 0
+fn dt_node(node: usize, what: impl core::fmt::Display) -> usize {
+    if node < MAX_NUMA_NODES {
+        return node;
+    }
+    println!("NUMA node {node} of {what} out of range, max {MAX_NUMA_NODES}, use node 0");
+    0
+}
+
+/// 超出 [`MAX_MEM_BLKS`] 的 bank 不记录，按节点 0 处理
+pub(crate) fn add_memory(range: Range<usize>, node: usize) {
+    let node = dt_node(node, format_args!("memory {range:#x?}"));
+    let mut numa = NUMA.lock();
+    numa.nodes = numa.nodes.max(node + 1);
+    if let Err((r, _)) = numa.mem.push((range, node)) {
+        println!("Too many NUMA memory blocks, {r:#x?} belongs to node 0");
+    }
+}
+
+/// 超出 [`MAX_CPUS`] 的 CPU 不记录，按节点 0 处理
+pub(crate) fn add_cpu(cpu_id: usize, node: usize) {
+    let node = dt_node(node, format_args!("CPU {cpu_id:#x}"));
+    let mut numa = NUMA.lock();
+    numa.nodes = numa.nodes.max(node + 1);
+    if numa.cpus.push((cpu_id, node)).is_err() {
+        println!("Too many CPUs, CPU {cpu_id:#x} belongs to node 0");
+    }
+}
+
+/// 与 Linux 相同，`from < to` 时同时设置反方向，之后的条目可以覆盖。超出范围的节点忽略
+pub(crate) fn set_distance(from: usize, to: usize, distance: u8) {
+    if from.max(to) >= MAX_NUMA_NODES {
+        println!("distance-map: node {from} -> {to} out of range, max {MAX_NUMA_NODES}, ignored");
+        return;
+    }
+    let mut numa = NUMA.lock();
+    numa.distance[from][to] = distance;
+    if to > from {
+        numa.distance[to][from] = distance;
+    }
+}
+
+/// 节点数
+pub fn node_count() -> usize {
+    NUMA.lock().nodes
+}
+
+/// CPU 所在节点，`cpu_id` 为逻辑 id
+pub fn node_of_cpu(cpu_id: usize) -> usize {
+    let Some(mpidr) = mpidr_of(cpu_id) else {
+        return 0;
+    };
+    NUMA.lock()
+        .cpus
+        .iter()
+        .find(|(id, _)| *id == mpidr)
+        .map_or(0, |&(_, node)| node)
+}
+
+/// 物理地址所在节点，不在 RAM 中时为 `None`
+pub fn node_of_paddr(paddr: usize) -> Option<usize> {
+    NUMA.lock()
+        .mem
+        .iter()
+        .find(|(r, _)| r.contains(&paddr))
+        .map(|&(_, node)| node)
+}
+
+/// 把 `region` 按节点切开，返回每段及其节点，不在任何节点 RAM 中的部分算节点 0
+pub fn region_nodes(region: &MemoryRegion) -> Vec<(Range<usize>, usize), { 2 * MAX_MEM_BLKS + 1 }> {
+    let numa = NUMA.lock();
+    let mut out: Vec<(Range<usize>, usize), { 2 * MAX_MEM_BLKS + 1 }> = Vec::new();
+    let mut pos = region.start;
+    while pos < region.end {
+        let (end, node) = match numa.mem.iter().find(|(r, _)| r.contains(&pos)) {
+            Some((r, node)) => (r.end, *node),
+            None => {
+                let next = numa
+                    .mem
+                    .iter()
+                    .map(|(r, _)| r.start)
+                    .filter(|&s| s > pos)
+                    .min();
+                (next.unwrap_or(region.end), 0)
+            }
+        };
+        let end = end.min(region.end);
+        match out.last_mut() {
+            Some((last, n)) if *n == node && last.end == pos => last.end = end,
+            _ => out
+                .push((pos..end, node))
+                .unwrap_or_else(|_| unreachable!("NUMA blocks are at most {MAX_MEM_BLKS}")),
+        }
+        pos = end;
+    }
+    out
+}
+
+/// 节点的 RAM，包括被 `mem=` 等裁掉的部分
+pub fn node_memory(node: usize) -> Vec<Range<usize>, MAX_MEM_BLKS> {
+    NUMA.lock()
+        .mem
+        .iter()
+        .filter(|(_, n)| *n == node)
+        .map(|(r, _)| r.clone())
+        .collect()
+}
+
+/// 两个节点间的距离
+pub fn distance(from: usize, to: usize) -> u8 {
+    NUMA.lock().distance[check_node(from)][check_node(to)]
+}
+
+/// 距离矩阵，只有前 [`node_count`] 行列有意义
+pub fn distance_matrix() -> [[u8; MAX_NUMA_NODES]; MAX_NUMA_NODES] {
+    NUMA.lock().distance
+}
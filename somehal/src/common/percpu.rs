@@ -0,0 +1,98 @@
+//! 每 CPU 数据。
+//!
+//! [`#[percpu]`](crate::percpu) 修饰的静态变量放在 `.percpu` 段中作为模板，启动时为每个从核复制一份，
+//! 放在其栈的上方，启动 CPU 直接使用模板。`TPIDR_EL1`（`hv` 时为 `TPIDR_EL2`）指向当前 CPU 的副本，
+//! 由 somehal 占用。
+
+use core::cell::UnsafeCell;
+
+use num_align::NumAlign;
+
+use crate::{
+    arch::percpu_base,
+    common::mem::{page_size, percpu_area},
+};
+
+/// `#[percpu]` 静态变量的类型，每个 CPU 一份 `T`
+pub struct PerCpu<T> {
+    value: UnsafeCell<T>,
+}
+
+// 各 CPU 的副本可能在别的 CPU 上访问，`T` 至少要能跨 CPU 传递；
+// 只有 `T: Sync` 时才能拿到引用，裸指针由调用者保证
+unsafe impl<T: Send> Sync for PerCpu<T> {}
+
+impl<T> PerCpu<T> {
+    #[doc(hidden)]
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    fn offset(&self) -> usize {
+        self.value.get() as usize - template().start
+    }
+
+    /// 当前 CPU 的副本
+    pub fn this_cpu_ptr(&self) -> *mut T {
+        (percpu_base() + self.offset()) as *mut T
+    }
+
+    /// 当前 CPU 的副本。线程被迁移到其它 CPU 后，引用仍指向原来的副本
+    pub fn this_cpu(&self) -> &T
+    where
+        T: Sync,
+    {
+        unsafe { &*self.this_cpu_ptr() }
+    }
+
+    /// 逻辑 id 为 `cpu_id` 的 CPU 的副本
+    ///
+    /// # Panics
+    /// `cpu_id` 超出 CPU 数时 panic。
+    pub fn per_cpu_ptr(&self, cpu_id: usize) -> *mut T {
+        (area_of(cpu_id) + self.offset()) as *mut T
+    }
+
+    /// `cpu_id` 的副本，见 [`PerCpu::per_cpu_ptr`]
+    pub fn per_cpu(&self, cpu_id: usize) -> &T
+    where
+        T: Sync,
+    {
+        unsafe { &*self.per_cpu_ptr(cpu_id) }
+    }
+}
+
+/// `.percpu` 模板的地址范围
+fn template() -> core::ops::Range<usize> {
+    unsafe extern "C" {
+        fn __percpu_start();
+        fn __percpu_end();
+    }
+    __percpu_start as usize..__percpu_end as usize
+}
+
+/// 启动 CPU 使用的区域，即模板本身
+pub(crate) fn boot_area() -> usize {
+    template().start
+}
+
+/// 每个从核的副本占用的大小，按页对齐
+pub(crate) fn area_size() -> usize {
+    template().len().align_up(page_size())
+}
+
+/// 把模板复制到 `dst`
+pub(crate) unsafe fn copy_template(dst: *mut u8) {
+    let src = template();
+    unsafe { core::ptr::copy_nonoverlapping(src.start as *const u8, dst, src.len()) };
+}
+
+/// 逻辑 id 为 `cpu_id` 的 CPU 的每 CPU 区域的虚拟地址
+pub(crate) fn area_of(cpu_id: usize) -> usize {
+    if cpu_id == 0 {
+        return boot_area();
+    }
+    percpu_area(cpu_id)
+}
@@ -2,6 +2,8 @@
 #![cfg(target_os = "none")]
 
 extern crate alloc;
+// 让宏生成的 `::somehal::...` 在本 crate 中也能使用
+extern crate self as somehal;
 
 #[cfg(target_arch = "aarch64")]
 #[path = "arch/aarch64/mod.rs"]
@@ -14,13 +16,19 @@ pub mod early_debug;
 mod lazy_static;
 mod loader;
 
-pub use common::entry::boot_info;
+pub use common::{
+    cmdline,
+    cpu::{cpu_logical_id, logical_of, mpidr_of},
+    entry::boot_info,
+    numa, percpu,
+};
+/// Link-time layout, see [`mem::va_layout`] for the one in use.
 pub use kdef_pgtable::{KIMAGE_VADDR, KIMAGE_VSIZE, KLINER_OFFSET};
 pub use pie_boot_if::{BootInfo, MemoryRegion, MemoryRegionKind, MemoryRegions};
 use pie_boot_loader_aarch64::EarlyBootArgs;
 #[allow(unused)]
 use pie_boot_macros::start_code;
-pub use pie_boot_macros::{entry, irq_handler, secondary_entry};
+pub use pie_boot_macros::{early_param, entry, irq_handler, percpu, secondary_entry};
 
 #[allow(unused)]
 #[unsafe(link_section = ".data")]
@@ -4,7 +4,8 @@ fn main() {
     println!("cargo:rustc-link-search={}", out_dir().display());
 
     println!("cargo::rustc-link-arg=-Tlink_test.x");
-    println!("cargo::rustc-link-arg-tests=-no-pie");
+    println!("cargo::rustc-link-arg-tests=-pie");
+    println!("cargo::rustc-link-arg-tests=-znotext");
     println!("cargo::rustc-link-arg-tests=-znostart-stop-gc");
     println!("cargo::rustc-link-arg-tests=-Map=target/kernel.map");
 
@@ -27,6 +27,9 @@ static CPU_STATED: AtomicUsize = AtomicUsize::new(1);
 static SHARED_DATA: Mutex<usize> = Mutex::new(0);
 const SHARED_DATA_WANTED: usize = 12345678;
 
+#[somehal::percpu]
+static THIS_CPU_ID: AtomicUsize = AtomicUsize::new(usize::MAX);
+
 #[somehal::entry]
 fn main(args: &BootInfo) -> ! {
     init_log();
@@ -44,8 +47,11 @@ fn main(args: &BootInfo) -> ! {
     let cpu_ls = cpu_id_list().collect::<Vec<_>>();
 
     for &cpu_id in &cpu_ls {
-        debug!("cpu id: {cpu_id:#x}");
-        if cpu_id == args.cpu_id {
+        debug!(
+            "cpu id: {cpu_id}, mpidr: {:#x}",
+            somehal::mpidr_of(cpu_id).unwrap()
+        );
+        if cpu_id == somehal::cpu_logical_id() {
             continue;
         }
         let stack = cpu_stack(cpu_id); // Example stack top address for the new CPU
@@ -57,6 +63,13 @@ fn main(args: &BootInfo) -> ! {
         core::hint::spin_loop();
     }
 
+    THIS_CPU_ID
+        .this_cpu()
+        .store(somehal::cpu_logical_id(), Ordering::SeqCst);
+    for &cpu_id in &cpu_ls {
+        assert_eq!(THIS_CPU_ID.per_cpu(cpu_id).load(Ordering::SeqCst), cpu_id);
+    }
+
     // unsafe {
     //     let a = 2usize as *mut u8;
     //     let b = a.read_volatile();
@@ -75,11 +88,13 @@ fn irq_handler() {
 #[somehal::secondary_entry]
 fn secondary(cpu_id: usize) {
     debug!("Secondary CPU {cpu_id} started");
+    assert_eq!(somehal::cpu_logical_id(), cpu_id);
     {
         let data = SHARED_DATA.lock();
         assert_eq!(*data, SHARED_DATA_WANTED);
         debug!("Secondary CPU {cpu_id} read shared data: {}", *data);
     }
+    THIS_CPU_ID.this_cpu().store(cpu_id, Ordering::SeqCst);
     CPU_STATED.fetch_add(1, Ordering::SeqCst);
     loop {
         core::hint::spin_loop();
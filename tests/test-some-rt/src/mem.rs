@@ -1,46 +1,52 @@
 use buddy_system_allocator::LockedHeap;
 use log::debug;
-use somehal::{MemoryRegionKind, boot_info, mem::phys_to_virt};
+use somehal::{
+    mem::{
+        MAX_ORDER, alloc_frames, alloc_frames_on, frame_stats, free_frames, page_size,
+        phys_to_virt, take_early_heap,
+    },
+    numa,
+};
 
 pub use somehal::mem::*;
 
 #[global_allocator]
 static HEAP_ALLOCATOR: LockedHeap<32> = LockedHeap::empty();
 
+const HEAP_SIZE: usize = 64 * 1024 * 1024;
+
 pub fn init_this() {
-    debug!("Initializing memory regions");
-    let main_start = boot_info().free_memory_start as usize;
-    for ram in boot_info()
-        .memory_regions
-        .iter()
-        .filter(|r| matches!(r.kind, MemoryRegionKind::Ram))
-    {
-        let start = ram.start;
-        let end = ram.end;
-        if (start..end).contains(&main_start) {
-            let start = phys_to_virt(main_start) as usize;
-            let end = phys_to_virt(end) as usize;
-            let size = end - start;
-            debug!("Initializing heap allocator: start={start:#x}, size={size:#x}");
-            unsafe { HEAP_ALLOCATOR.lock().init(start, size) };
+    let early = take_early_heap().expect("early heap already taken");
+    debug!("Early heap: [{:#x}, {:#x})", early.start, early.end);
+    unsafe {
+        HEAP_ALLOCATOR
+            .lock()
+            .init(early.start, early.end - early.start)
+    };
+
+    let stats = frame_stats(None);
+    debug!(
+        "Frames: total {:#x}, free {:#x}",
+        stats.total * page_size(),
+        stats.free * page_size()
+    );
+
+    let node = numa::node_of_cpu(0);
+    let paddr = alloc_frames_on(node, 0).expect("no frame on the boot CPU's node");
+    assert_eq!(numa::node_of_paddr(paddr).unwrap_or(0), node);
+    free_frames(paddr, 0);
+
+    let block = page_size() << MAX_ORDER;
+    let mut size = 0;
+    while size < HEAP_SIZE.min(stats.free * page_size() / 2) {
+        let Some(paddr) = alloc_frames(MAX_ORDER) else {
             break;
-        }
-    }
-    for ram in boot_info()
-        .memory_regions
-        .iter()
-        .filter(|r| matches!(r.kind, MemoryRegionKind::Ram))
-    {
-        let start = ram.start;
-        let end = ram.end;
-        if !(start..end).contains(&main_start) {
-            let start = phys_to_virt(start) as usize;
-            let end = phys_to_virt(end) as usize;
-            let size = end - start;
-            debug!("Adding to heap allocator: start={start:#x}, size={size:#x}");
-            unsafe { HEAP_ALLOCATOR.lock().add_to_heap(start, end) };
-        }
+        };
+        let start = phys_to_virt(paddr) as usize;
+        unsafe { HEAP_ALLOCATOR.lock().add_to_heap(start, start + block) };
+        size += block;
     }
+    debug!("Heap allocator: {size:#x}");
 
     init();
 }